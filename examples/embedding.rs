@@ -0,0 +1,33 @@
+// Registering Rust functions with the VM so scripts can call them.
+// Run with: cargo run --example embedding
+use nail::vm::{InterpreterError, VM};
+use nail::{FromNail, IntoNail, Value};
+
+fn main() {
+    let mut vm = VM::new();
+    vm.register_fn("hypot", 2, |_, args| match (&args[0], &args[1]) {
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.hypot(*b))),
+        _ => Err(InterpreterError::RuntimeError(
+            "hypot expects two numbers".to_string(),
+            0,
+            Vec::new(),
+        )),
+    });
+    // FromNail and IntoNail convert arguments and results, allocating arrays
+    // and maps on the heap as needed.
+    vm.register_fn("squares", 1, |heap, args| {
+        let n = usize::from_nail(&args[0], heap)?;
+        let squares: Vec<usize> = (0..n).map(|i| i * i).collect();
+        squares.into_nail(heap)
+    });
+
+    let script = r#"
+        fn main() {
+            print hypot(3, 4);
+            print squares(5);
+        }
+    "#;
+    if let Err(e) = vm.interpret(script) {
+        println!("{}", e);
+    }
+}
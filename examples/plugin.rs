@@ -0,0 +1,42 @@
+// Loading a script once and calling its functions from Rust, e.g. for
+// plugins or configuration callbacks.
+// Run with: cargo run --example plugin
+use nail::vm::VM;
+use nail::Value;
+
+fn main() {
+    let mut vm = VM::new();
+    let script = r#"
+        let greetings = 0;
+
+        fn greet(name) {
+            greetings += 1;
+            "Hello, {name}!"
+        }
+
+        fn count() {
+            greetings
+        }
+    "#;
+    if let Err(e) = vm.load(script) {
+        println!("{}", e);
+        return;
+    }
+    // call returns a handle, keeping the result alive until it's released.
+    for name in &["Ferris", "Nail"] {
+        match vm.call("greet", &[Value::String(name.to_string())]) {
+            Ok(h) => {
+                let greeting = vm.release(h);
+                println!("{}", vm.display_value(&greeting));
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+    match vm.call("count", &[]) {
+        Ok(h) => {
+            let count = vm.release(h);
+            println!("greeted {} times", vm.display_value(&count));
+        }
+        Err(e) => println!("{}", e),
+    }
+}
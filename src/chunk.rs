@@ -1,4 +1,4 @@
-use super::value::Value;
+use super::value::{HashableValue, Value};
 use std::collections::HashMap;
 
 #[derive(Copy, Clone)]
@@ -66,8 +66,55 @@ pub enum OpCode {
 
     AssignGlobal = 40,
     LoadGlobal = 41,
+
+    Closure = 42,
+    LoadUpvalue = 43,
+    AssignUpvalue = 44,
+    CloseUpvalues = 45,
+
+    NewInstance = 46,
+    InitField = 47,
+    GetField = 48,
+    SetField = 49,
+
+    NewEnum = 50,
+    MatchVariant = 51,
+    VariantPayload = 52,
+    MatchArray = 53,
+    MatchRange = 54,
+    NoMatch = 55,
+    MatchArrayAtLeast = 56,
+    ArrayRest = 57,
+    MatchKey = 58,
+    GetKey = 59,
+
+    Interpolate = 60,
+
+    PushHandler = 61,
+    PopHandler = 62,
+    Throw = 63,
+    Exit = 64,
+
+    ConstantLong = 65,
+
+    JumpLong = 66,
+    JumpIfFalseLong = 67,
+    JumpIfTrueLong = 68,
+    ForLoopLong = 69,
+    PushHandlerLong = 70,
+
+    LoadLocalLong = 71,
+    AssignLocalLong = 72,
+
+    BadDestructure = 73,
 }
 
+// Operands of BadDestructure, saying which kind of pattern test failed.
+pub const EXPECTED_ARRAY: u8 = 0;
+pub const EXPECTED_ARRAY_AT_LEAST: u8 = 1;
+pub const EXPECTED_KEY: u8 = 2;
+pub const EXPECTED_VALUE: u8 = 3;
+
 impl OpCode {
     pub fn try_from(val: u8) -> Option<Self> {
         match val {
@@ -135,19 +182,106 @@ impl OpCode {
             40 => Some(OpCode::AssignGlobal),
             41 => Some(OpCode::LoadGlobal),
 
+            42 => Some(OpCode::Closure),
+            43 => Some(OpCode::LoadUpvalue),
+            44 => Some(OpCode::AssignUpvalue),
+            45 => Some(OpCode::CloseUpvalues),
+
+            46 => Some(OpCode::NewInstance),
+            47 => Some(OpCode::InitField),
+            48 => Some(OpCode::GetField),
+            49 => Some(OpCode::SetField),
+
+            50 => Some(OpCode::NewEnum),
+            51 => Some(OpCode::MatchVariant),
+            52 => Some(OpCode::VariantPayload),
+            53 => Some(OpCode::MatchArray),
+            54 => Some(OpCode::MatchRange),
+            55 => Some(OpCode::NoMatch),
+            56 => Some(OpCode::MatchArrayAtLeast),
+            57 => Some(OpCode::ArrayRest),
+            58 => Some(OpCode::MatchKey),
+            59 => Some(OpCode::GetKey),
+
+            60 => Some(OpCode::Interpolate),
+
+            61 => Some(OpCode::PushHandler),
+            62 => Some(OpCode::PopHandler),
+            63 => Some(OpCode::Throw),
+            64 => Some(OpCode::Exit),
+
+            65 => Some(OpCode::ConstantLong),
+
+            66 => Some(OpCode::JumpLong),
+            67 => Some(OpCode::JumpIfFalseLong),
+            68 => Some(OpCode::JumpIfTrueLong),
+            69 => Some(OpCode::ForLoopLong),
+            70 => Some(OpCode::PushHandlerLong),
+
+            71 => Some(OpCode::LoadLocalLong),
+            72 => Some(OpCode::AssignLocalLong),
+
+            73 => Some(OpCode::BadDestructure),
+
             _ => None,
         }
     }
 }
 
-#[derive(Default)]
+// Field names are numbered program wide, so a field access can be compiled
+// without knowing the struct type. Each struct maps field numbers to the
+// slot holding that field in its instances.
+#[derive(Clone)]
+pub struct StructDefinition {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub slots: Vec<Option<u8>>,
+    pub methods: HashMap<String, usize>,
+}
+
+impl StructDefinition {
+    pub fn slot(&self, field_number: usize) -> Option<u8> {
+        self.slots.get(field_number).cloned().flatten()
+    }
+}
+
+#[derive(Clone)]
+pub struct VariantDefinition {
+    pub name: String,
+    pub arity: u8,
+}
+
+#[derive(Clone)]
+pub struct EnumDefinition {
+    pub name: String,
+    pub variants: Vec<VariantDefinition>,
+}
+
+impl EnumDefinition {
+    pub fn variant(&self, name: &str) -> Option<u8> {
+        self.variants
+            .iter()
+            .position(|v| v.name == name)
+            .map(|v| v as u8)
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct Chunk {
     pub code: Vec<u8>,
     pub lines: Vec<usize>,
     pub constants: Vec<Value>,
     pub globals: HashMap<String, Value>,
-    pub function_names: std::collections::HashMap<String, u8>,
+    pub function_names: std::collections::HashMap<String, usize>,
     pub function_locations: Vec<usize>,
+    pub function_arities: Vec<u8>,
+    pub structs: Vec<StructDefinition>,
+    pub struct_names: HashMap<String, u8>,
+    pub field_names: Vec<String>,
+    pub field_numbers: HashMap<String, u8>,
+    pub enums: Vec<EnumDefinition>,
+    pub enum_names: HashMap<String, u8>,
+    constant_numbers: HashMap<HashableValue, usize>,
 }
 
 impl Chunk {
@@ -160,33 +294,101 @@ impl Chunk {
         self.lines.push(line);
     }
 
-    pub fn add_constant(&mut self, value: Value) -> u8 {
+    // Identical constants share a slot.
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        let key = HashableValue::try_from(&value, 0).ok();
+        if let Some(n) = key.as_ref().and_then(|k| self.constant_numbers.get(k)) {
+            return *n;
+        }
         self.constants.push(value);
-        (self.constants.len() - 1) as u8
+        let n = self.constants.len() - 1;
+        if let Some(k) = key {
+            self.constant_numbers.insert(k, n);
+        }
+        n
     }
 
-    pub fn register_function(&mut self, name: String, _arity: u8) {
-        use std::collections::hash_map::Entry;
-        if let Entry::Vacant(v) = self.function_names.entry(name) {
-            v.insert(self.function_locations.len() as u8);
-            self.function_locations.push(0);
-        }
+    pub fn register_function(&mut self, name: String, arity: u8) -> usize {
+        let number = self.function_locations.len();
+        self.function_names.insert(name, number);
+        self.function_locations.push(0);
+        self.function_arities.push(arity);
+        number
     }
 
-    pub fn start_function(&mut self, name: &str, line: usize) -> usize {
+    // Returns the address of the function's two byte locals count.
+    pub fn start_function(&mut self, number: usize, line: usize) -> usize {
         let address = self.code.len();
         self.code.push(OpCode::FunctionEntry as u8);
         self.lines.push(line);
         let ret = self.code.len();
-        self.code.push(0);
-        self.lines.push(line);
-        self.function_locations[self.function_names[name] as usize] = address;
+        for _ in 0..2 {
+            self.code.push(0);
+            self.lines.push(line);
+        }
+        self.function_locations[number] = address;
         ret
     }
 
-    pub fn lookup_function(&self, name: &str) -> usize {
-        let number = self.function_names[name];
-        self.function_locations[number as usize]
+    pub fn function_name(&self, number: usize) -> &str {
+        self.function_names
+            .iter()
+            .find(|(_, n)| **n == number)
+            .map(|(name, _)| name.as_str())
+            .unwrap_or("<unknown>")
+    }
+
+    pub fn lookup_function(&self, name: &str) -> Option<usize> {
+        let number = *self.function_names.get(name)?;
+        Some(self.function_locations[number])
+    }
+
+    // Struct, enum and field numbers are byte operands, so these return None
+    // once all 256 are used.
+    pub fn register_struct(&mut self, name: String, fields: Vec<String>) -> Option<u8> {
+        if self.structs.len() > u8::MAX as usize || fields.len() > u8::MAX as usize + 1 {
+            return None;
+        }
+        let mut slots = Vec::new();
+        for (slot, field) in fields.iter().enumerate() {
+            let number = self.register_field(field)? as usize;
+            if number >= slots.len() {
+                slots.resize(number + 1, None);
+            }
+            slots[number] = Some(slot as u8);
+        }
+        let number = self.structs.len() as u8;
+        self.struct_names.insert(name.clone(), number);
+        self.structs.push(StructDefinition {
+            name,
+            fields,
+            slots,
+            methods: HashMap::new(),
+        });
+        Some(number)
+    }
+
+    pub fn register_enum(&mut self, name: String, variants: Vec<VariantDefinition>) -> Option<u8> {
+        if self.enums.len() > u8::MAX as usize || variants.len() > u8::MAX as usize + 1 {
+            return None;
+        }
+        let number = self.enums.len() as u8;
+        self.enum_names.insert(name.clone(), number);
+        self.enums.push(EnumDefinition { name, variants });
+        Some(number)
+    }
+
+    pub fn register_field(&mut self, name: &str) -> Option<u8> {
+        if let Some(number) = self.field_numbers.get(name) {
+            return Some(*number);
+        }
+        if self.field_names.len() > u8::MAX as usize {
+            return None;
+        }
+        let number = self.field_names.len() as u8;
+        self.field_numbers.insert(name.to_string(), number);
+        self.field_names.push(name.to_string());
+        Some(number)
     }
 
     pub fn register_global(&mut self, name: &str, value: Value) {
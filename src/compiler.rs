@@ -3,93 +3,439 @@
 // Actually this lint is being allowed by default in future, so this can probably be ignored.
 
 use super::{
-    chunk, chunk::OpCode, debug, errors::NotloxError::CompilerError, errors::Result, parser,
-    scanner, scanner::TokenType, value,
+    chunk, chunk::OpCode, debug, errors::NotloxError, errors::NotloxError::CompilerError,
+    errors::Result, parser, scanner, scanner::TokenType, value,
 };
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Diagnostics printed while compiling, all off by default.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct CompileOptions {
+    pub dump_tokens: bool,
+    pub dump_ast: bool,
+    pub dump_bytecode: bool,
+}
+
+impl CompileOptions {
+    fn dump_source(&self, source: &str) -> Result<()> {
+        if self.dump_tokens {
+            debug::print_tokens(source)?;
+        }
+        Ok(())
+    }
+
+    fn dump_ast<T: std::fmt::Debug>(&self, ast: &T) {
+        if self.dump_ast {
+            println!("{:#?}", ast);
+        }
+    }
 
-pub fn compile(source: &str) -> Result<chunk::Chunk> {
+    fn dump_chunk(&self, chunk: &chunk::Chunk, name: &str) {
+        if self.dump_bytecode {
+            debug::disassemble_chunk(chunk, name);
+        }
+    }
+}
+
+// path is the file the source was read from, if any. Imports are resolved
+// relative to its directory (or the working directory without one).
+// natives are the names of the host's native functions, in order of their
+// numbers.
+pub fn compile(
+    source: &str,
+    path: Option<&Path>,
+    options: &CompileOptions,
+    natives: &[String],
+) -> Result<chunk::Chunk> {
+    options.dump_source(source)?;
     let ast = parser::parse(source)?;
+    options.dump_ast(&ast);
     let mut compiler = Compiler::new();
-    compiler.compile_program(ast)?;
-    debug::disassemble_chunk(&compiler.chunk, "foo.nlx");
+    compiler.natives = natives.to_vec();
+    if let Some(path) = path {
+        compiler.directory = path.parent().map(Path::to_path_buf);
+        if let Ok(path) = path.canonicalize() {
+            compiler.loader.loading.push(path);
+        }
+    }
+    compiler.compile_with_wide_jumps(|c| c.compile_program(ast.clone()))?;
+    let name = path.map_or("<script>".into(), |p| p.to_string_lossy());
+    options.dump_chunk(&compiler.chunk, &name);
     Ok(compiler.chunk)
 }
 
+const REPL_ENTRY: &str = "<repl>";
+// Largest operand of the wide instructions, which take three bytes.
+const MAX_LONG_OPERAND: usize = 0xFF_FFFF;
+// FunctionEntry gives the number of local slots in 16 bits.
+const MAX_LOCALS: usize = 0xFFFF;
+
+// Longer interpolations are joined in several Interpolate ops, keeping the
+// part count in its byte operand and the stack usage small.
+const MAX_INTERPOLATION_PARTS: usize = 64;
+
+// Compiler state kept between REPL entries, so each entry can use the
+// functions, globals and types defined by earlier ones.
+pub struct Session {
+    compiler: Compiler,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            compiler: Compiler::new(),
+        }
+    }
+
+    // Compile source into chunk as the body of the REPL entry function,
+    // returning its address. Imports are resolved relative to path, as for
+    // compile. On error, neither the chunk nor the session are changed.
+    pub fn compile(
+        &mut self,
+        source: &str,
+        path: Option<&Path>,
+        chunk: &mut chunk::Chunk,
+        options: &CompileOptions,
+        natives: &[String],
+    ) -> Result<usize> {
+        options.dump_source(source)?;
+        let entry = parser::parse_repl(source)?;
+        options.dump_ast(&entry);
+        let mut compiler = self.compiler.clone();
+        compiler.chunk = chunk.clone();
+        compiler.natives = natives.to_vec();
+        compiler.directory = path.and_then(Path::parent).map(Path::to_path_buf);
+        let address = compiler.compile_with_wide_jumps(|c| c.compile_repl_entry(entry.clone()))?;
+        *chunk = std::mem::take(&mut compiler.chunk);
+        options.dump_chunk(chunk, REPL_ENTRY);
+        self.compiler = compiler;
+        Ok(address)
+    }
+}
+
 enum ResolvedSymbol {
-    Local(u8),
-    Const(u8),
-    Global,
+    Local(usize),
+    Upvalue(usize),
+    Const(usize),
+    Global(String),
     Undefined,
 }
 
+#[derive(Clone)]
 struct Environment {
-    locals: HashMap<String, u8>,
-    consts: HashMap<String, u8>,
-    next_local: u8,
+    locals: HashMap<String, usize>,
+    consts: HashMap<String, usize>,
+    first_local: usize,
+    next_local: usize,
+    captured: bool,
 }
 
 impl Environment {
-    fn new(next_local: u8) -> Self {
+    fn new(next_local: usize) -> Self {
         Self {
             locals: HashMap::new(),
             consts: HashMap::new(),
+            first_local: next_local,
             next_local,
+            captured: false,
         }
     }
 }
 
+fn lookup_symbol(environments: &mut [Environment], name: &str) -> Option<ResolvedSymbol> {
+    for e in environments.iter_mut().rev() {
+        // Todo: Ensure ordering works properly.
+        // Currently locals will always be found before consts.
+        // May need a stack of both local and const definitions in an env.
+        if let Some(n) = e.locals.get(name) {
+            return Some(ResolvedSymbol::Local(*n));
+        } else if let Some(n) = e.consts.get(name) {
+            return Some(ResolvedSymbol::Const(*n));
+        }
+    }
+    None
+}
+
+fn capture_local(environments: &mut [Environment], name: &str) {
+    for e in environments.iter_mut().rev() {
+        if e.locals.contains_key(name) {
+            e.captured = true;
+            return;
+        }
+    }
+}
+
+fn is_irrefutable(pattern: &parser::Pattern) -> bool {
+    matches!(
+        pattern,
+        parser::Pattern::Wildcard | parser::Pattern::Binding(..)
+    )
+}
+
+// Argument counts are encoded in a byte.
+fn arg_count(count: usize, line: usize) -> Result<u8> {
+    if count > u8::MAX as usize {
+        Err(CompilerError(format!("Too many arguments (line {})", line)))
+    } else {
+        Ok(count as u8)
+    }
+}
+
+// A pattern test's jump to where matching fails, with what the test
+// expected so destructuring can report it.
+struct MatchFailure {
+    jump: usize,
+    // The local holding the tested value.
+    source: usize,
+    expected: Expected,
+}
+
+enum Expected {
+    Array(u8),
+    ArrayAtLeast(u8),
+    Key(String),
+    Value(String),
+}
+
+fn too_many_fields(line: usize) -> NotloxError {
+    CompilerError(format!("Too many distinct field names (line {})", line))
+}
+
+fn describe_literal(literal: &parser::Literal) -> String {
+    match literal {
+        parser::Literal::Number(n, _) => n.to_string(),
+        parser::Literal::String(s, _) => format!("{:?}", s),
+        parser::Literal::Char(c, _) => format!("{:?}", c),
+        parser::Literal::False(_) => "false".to_string(),
+        parser::Literal::True(_) => "true".to_string(),
+        parser::Literal::Nil(_) => "nil".to_string(),
+    }
+}
+
+fn literal_line(literal: &parser::Literal) -> usize {
+    match literal {
+        parser::Literal::Number(_, line)
+        | parser::Literal::String(_, line)
+        | parser::Literal::Char(_, line)
+        | parser::Literal::False(line)
+        | parser::Literal::True(line)
+        | parser::Literal::Nil(line) => *line,
+    }
+}
+
+// Renames each name a pattern binds to a hidden local, collecting the
+// original names, so a destructuring let at the REPL can copy them to globals.
+fn hide_bindings(pattern: &mut parser::Pattern, names: &mut Vec<String>) {
+    match pattern {
+        parser::Pattern::Binding(name, _) => {
+            names.push(name.clone());
+            *name = format!("<let {}>", name);
+        }
+        parser::Pattern::Array(array) => {
+            for p in array.elements.iter_mut() {
+                hide_bindings(p, names);
+            }
+            if let Some(rest) = array.rest.as_mut() {
+                hide_bindings(rest, names);
+            }
+        }
+        parser::Pattern::Map(keys, _) => {
+            for k in keys.iter_mut() {
+                hide_bindings(&mut k.pattern, names);
+            }
+        }
+        parser::Pattern::Variant(v) => {
+            for p in v.patterns.iter_mut() {
+                hide_bindings(p, names);
+            }
+        }
+        parser::Pattern::Wildcard | parser::Pattern::Literal(_) | parser::Pattern::Range(..) => {}
+    }
+}
+
+fn module_error(path: &Path, error: NotloxError) -> NotloxError {
+    match error {
+        // Errors from further down the import chain already say where they
+        // came from.
+        CompilerError(message) if message.starts_with("In module ") => CompilerError(message),
+        CompilerError(message) => {
+            CompilerError(format!("In module {}: {}", path.display(), message))
+        }
+        error => CompilerError(format!("In module {}: {}", path.display(), error)),
+    }
+}
+
+// An imported module as seen by the files importing it. Its functions and
+// globals are registered in the chunk with prefix in front of their names.
+#[derive(Clone)]
+struct Module {
+    prefix: String,
+    consts: HashMap<String, usize>,
+}
+
+// Module state shared by every file compiled into a chunk. Each module is
+// only compiled once, however many times it is imported.
+#[derive(Default, Clone)]
+struct ModuleLoader {
+    modules: HashMap<PathBuf, Module>,
+    // Files currently being compiled, innermost last, for detecting cycles.
+    loading: Vec<PathBuf>,
+    search_path: Vec<PathBuf>,
+}
+
+impl ModuleLoader {
+    fn new() -> Self {
+        let search_path = match std::env::var_os("NAIL_PATH") {
+            Some(paths) => std::env::split_paths(&paths).collect(),
+            None => Vec::new(),
+        };
+        Self {
+            search_path,
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+struct UpvalueDescriptor {
+    is_local: bool,
+    index: usize,
+}
+
+// Compiler state belonging to a function whose body is being compiled.
+// When a nested function starts, the enclosing function's state is saved
+// here so names can be resolved through it as upvalues.
+#[derive(Clone)]
+struct FunctionContext {
+    name: String,
+    environments: Vec<Environment>,
+    loop_contexts: Vec<LoopContext>,
+    upvalues: Vec<UpvalueDescriptor>,
+    max_local: usize,
+    pushed_this_fn: u8,
+}
+
+#[derive(Clone)]
 struct LoopContext {
     continue_address: usize,
     pushed_this_loop: u8,
     breaks: Vec<usize>,
     break_pop: bool,
+    // Number of try blocks entered inside the loop, whose handlers must be
+    // popped when breaking out of it.
+    handlers: u8,
+    // Number of environments outside the loop. Those above it are exited by
+    // break and continue.
+    environments: usize,
 }
 
 impl LoopContext {
-    fn new(continue_address: usize, break_pop: bool) -> Self {
+    fn new(continue_address: usize, break_pop: bool, environments: usize) -> Self {
         Self {
             continue_address,
             pushed_this_loop: 0,
             breaks: Vec::new(),
             break_pop,
+            handlers: 0,
+            environments,
         }
     }
 }
 
+#[derive(Clone)]
 struct Compiler {
     chunk: chunk::Chunk,
+    function_name: String,
     environments: Vec<Environment>,
     loop_contexts: Vec<LoopContext>,
-    deferred: Vec<(parser::FnStatement, u8)>,
-    max_local: u8,
+    upvalues: Vec<UpvalueDescriptor>,
+    enclosing: Vec<FunctionContext>,
+    max_local: usize,
     pushed_this_fn: u8,
+    // Prepended to the names of this file's functions and globals, empty
+    // for the main file.
+    module_prefix: String,
+    directory: Option<PathBuf>,
+    imports: HashMap<String, Module>,
+    loader: ModuleLoader,
+    natives: Vec<String>,
+    wide_jumps: bool,
+    // Set when a jump didn't fit in its offset.
+    jump_overflow: bool,
 }
 
 impl Compiler {
     fn new() -> Self {
         Compiler {
             chunk: chunk::Chunk::new(),
+            function_name: String::new(),
             environments: vec![Environment::new(0)],
-            loop_contexts: vec![LoopContext::new(0, false)],
-            deferred: Vec::new(),
+            loop_contexts: vec![LoopContext::new(0, false, 1)],
+            upvalues: Vec::new(),
+            enclosing: Vec::new(),
             max_local: 0,
             pushed_this_fn: 0,
+            module_prefix: String::new(),
+            directory: None,
+            imports: HashMap::new(),
+            loader: ModuleLoader::new(),
+            natives: Vec::new(),
+            wide_jumps: false,
+            jump_overflow: false,
         }
     }
 
+    fn begin_function(&mut self, name: String) {
+        let enclosing = FunctionContext {
+            name: std::mem::replace(&mut self.function_name, name),
+            environments: std::mem::replace(&mut self.environments, vec![Environment::new(0)]),
+            loop_contexts: std::mem::replace(
+                &mut self.loop_contexts,
+                vec![LoopContext::new(0, false, 1)],
+            ),
+            upvalues: std::mem::take(&mut self.upvalues),
+            max_local: self.max_local,
+            pushed_this_fn: self.pushed_this_fn,
+        };
+        self.enclosing.push(enclosing);
+        self.max_local = 0;
+        self.pushed_this_fn = 0;
+    }
+
+    fn end_function(&mut self) -> Vec<UpvalueDescriptor> {
+        let enclosing = self.enclosing.pop().unwrap();
+        self.function_name = enclosing.name;
+        self.environments = enclosing.environments;
+        self.loop_contexts = enclosing.loop_contexts;
+        self.max_local = enclosing.max_local;
+        self.pushed_this_fn = enclosing.pushed_this_fn;
+        std::mem::replace(&mut self.upvalues, enclosing.upvalues)
+    }
+
     fn push_environment(&mut self) {
         let new_env = Environment::new(self.environments.last().unwrap().next_local);
         self.environments.push(new_env);
     }
 
-    fn pop_environment(&mut self) {
-        self.environments.pop();
+    fn pop_environment(&mut self, line: usize) {
+        let env = self.environments.pop().unwrap();
+        if env.captured {
+            self.chunk.write_chunk(OpCode::CloseUpvalues as u8, line);
+            self.emit_short(env.first_local, line);
+        }
     }
 
     fn push_loop_context(&mut self, continue_address: usize, break_pop: bool) {
+        let environments = self.environments.len();
         self.loop_contexts
-            .push(LoopContext::new(continue_address, break_pop));
+            .push(LoopContext::new(continue_address, break_pop, environments));
     }
 
     fn pop_loop_context(&mut self, break_address: usize) {
@@ -105,58 +451,470 @@ impl Compiler {
             (self.loop_contexts.last().unwrap().pushed_this_loop as i8 + usage) as u8;
     }
 
-    fn find_symbol(&self, name: &str) -> ResolvedSymbol {
-        for e in self.environments.iter().rev() {
-            // Todo: Ensure ordering works properly.
-            // Currently locals will always be found before consts.
-            // May need a stack of both local and const definitions in an env.
-            if let Some(n) = e.locals.get(name) {
-                return ResolvedSymbol::Local(*n);
-            } else if let Some(n) = e.consts.get(name) {
-                return ResolvedSymbol::Const(*n);
-            }
+    fn find_symbol(&mut self, name: &str) -> ResolvedSymbol {
+        if let Some(symbol) = lookup_symbol(&mut self.environments, name) {
+            return symbol;
         }
-        if self.chunk.check_global(name) {
-            ResolvedSymbol::Global
+        if let Some(symbol) = self.resolve_upvalue(self.enclosing.len(), name) {
+            return symbol;
+        }
+        let global = format!("{}{}", self.module_prefix, name);
+        if self.chunk.check_global(&global) {
+            ResolvedSymbol::Global(global)
+        } else if let Some(n) = self.natives.iter().position(|n| n == name) {
+            ResolvedSymbol::Const(self.chunk.add_constant(value::Value::NativeFunction(n)))
         } else {
             ResolvedSymbol::Undefined
         }
     }
 
+    // If object names an imported module (and isn't shadowed by a variable),
+    // resolve name as a member of that module.
+    fn find_module_member(
+        &mut self,
+        object: &parser::Expression,
+        name: &str,
+        line: usize,
+    ) -> Result<Option<ResolvedSymbol>> {
+        let module_name = match object {
+            parser::Expression::Variable(v) => &v.name,
+            _ => return Ok(None),
+        };
+        if !self.imports.contains_key(module_name) {
+            return Ok(None);
+        }
+        if !matches!(self.find_symbol(module_name), ResolvedSymbol::Undefined) {
+            return Ok(None);
+        }
+        let module = &self.imports[module_name];
+        if let Some(c) = module.consts.get(name) {
+            return Ok(Some(ResolvedSymbol::Const(*c)));
+        }
+        let global = format!("{}{}", module.prefix, name);
+        if self.chunk.check_global(&global) {
+            Ok(Some(ResolvedSymbol::Global(global)))
+        } else {
+            Err(CompilerError(format!(
+                "Module {} has no member {} (line {})",
+                module_name, name, line
+            )))
+        }
+    }
+
+    // Resolve name in the functions enclosing the one at depth (where depth
+    // enclosing.len() is the function currently being compiled), adding
+    // upvalues to each function in between as needed.
+    fn resolve_upvalue(&mut self, depth: usize, name: &str) -> Option<ResolvedSymbol> {
+        if depth == 0 {
+            return None;
+        }
+        let environments = &mut self.enclosing[depth - 1].environments;
+        match lookup_symbol(environments, name) {
+            Some(ResolvedSymbol::Local(n)) => {
+                capture_local(environments, name);
+                Some(ResolvedSymbol::Upvalue(self.add_upvalue(depth, true, n)))
+            }
+            Some(symbol) => Some(symbol),
+            None => match self.resolve_upvalue(depth - 1, name)? {
+                ResolvedSymbol::Upvalue(n) => {
+                    Some(ResolvedSymbol::Upvalue(self.add_upvalue(depth, false, n)))
+                }
+                symbol => Some(symbol),
+            },
+        }
+    }
+
+    fn add_upvalue(&mut self, depth: usize, is_local: bool, index: usize) -> usize {
+        let upvalues = if depth == self.enclosing.len() {
+            &mut self.upvalues
+        } else {
+            &mut self.enclosing[depth].upvalues
+        };
+        let upvalue = UpvalueDescriptor { is_local, index };
+        if let Some(n) = upvalues.iter().position(|u| *u == upvalue) {
+            return n;
+        }
+        upvalues.push(upvalue);
+        upvalues.len() - 1
+    }
+
     fn compile_program(&mut self, program: parser::Program) -> Result<()> {
+        for d in &program.statements {
+            if let parser::Statement::ImportStatement(i) = d {
+                self.compile_import(i)?;
+            }
+        }
+
+        // Structs and enums are declared up front so they can be used before
+        // (textually) their definition.
+        for d in &program.statements {
+            match d {
+                parser::Statement::StructStatement(s) => self.declare_struct(s)?,
+                parser::Statement::EnumStatement(e) => self.declare_enum(e)?,
+                _ => {}
+            }
+        }
+
         for d in program.statements {
             self.compile_statement(d, true)?;
         }
 
-        while let Some((fn_statement, constant)) = self.deferred.pop() {
-            let name = fn_statement.name.clone();
-            self.compile_fn_statement(fn_statement, true)?;
-            let addr = self.chunk.lookup_function(&name);
-            self.chunk.constants[constant as usize] = value::Value::Callable(addr);
+        Ok(())
+    }
+
+    // Declarations in a REPL entry are compiled at the top level, so later
+    // entries can see them. Everything else goes in the entry function, with
+    // lets turned into assignments to globals rather than locals.
+    fn compile_repl_entry(&mut self, entry: parser::Block) -> Result<usize> {
+        for s in &entry.statements {
+            match s {
+                parser::Statement::ImportStatement(i) => self.compile_import(i)?,
+                parser::Statement::StructStatement(s) => self.declare_struct(s)?,
+                parser::Statement::EnumStatement(e) => self.declare_enum(e)?,
+                parser::Statement::LetStatement(l) if !self.chunk.check_global(&l.name) => {
+                    self.chunk.register_global(&l.name, value::Value::Nil);
+                }
+                parser::Statement::DestructuringLetStatement(d) => {
+                    let mut names = Vec::new();
+                    hide_bindings(&mut d.pattern.clone(), &mut names);
+                    for name in names {
+                        if !self.chunk.check_global(&name) {
+                            self.chunk.register_global(&name, value::Value::Nil);
+                        }
+                    }
+                }
+                _ => {}
+            }
         }
 
-        Ok(())
+        let mut statements = Vec::new();
+        for s in entry.statements {
+            match s {
+                parser::Statement::LetStatement(l) => {
+                    let value = l
+                        .initializer
+                        .unwrap_or(parser::Expression::Literal(parser::Literal::Nil(l.line)));
+                    statements.push(parser::Statement::ExpressionStatement(
+                        parser::ExpressionStatement {
+                            expression: parser::Expression::Assignment(parser::Assignment {
+                                lvalue: parser::LValue::Variable(parser::Variable {
+                                    name: l.name,
+                                    line: l.line,
+                                }),
+                                value: Box::new(value),
+                                line: l.line,
+                            }),
+                            line: l.line,
+                        },
+                    ));
+                }
+                // The pattern binds hidden locals of the entry function, which
+                // are then assigned to the globals.
+                parser::Statement::DestructuringLetStatement(mut d) => {
+                    let mut names = Vec::new();
+                    hide_bindings(&mut d.pattern, &mut names);
+                    let line = d.line;
+                    statements.push(parser::Statement::DestructuringLetStatement(d));
+                    for name in names {
+                        let hidden = parser::Expression::Variable(parser::Variable {
+                            name: format!("<let {}>", name),
+                            line,
+                        });
+                        statements.push(parser::Statement::ExpressionStatement(
+                            parser::ExpressionStatement {
+                                expression: parser::Expression::Assignment(parser::Assignment {
+                                    lvalue: parser::LValue::Variable(parser::Variable {
+                                        name,
+                                        line,
+                                    }),
+                                    value: Box::new(hidden),
+                                    line,
+                                }),
+                                line,
+                            },
+                        ));
+                    }
+                }
+                parser::Statement::FnStatement(_)
+                | parser::Statement::ConstStatement(_)
+                | parser::Statement::StructStatement(_)
+                | parser::Statement::EnumStatement(_)
+                | parser::Statement::ImplStatement(_)
+                | parser::Statement::ImportStatement(_) => self.compile_statement(s, true)?,
+                s => statements.push(s),
+            }
+        }
+
+        let number = match self.chunk.function_names.get(REPL_ENTRY) {
+            Some(n) => *n,
+            None => self.chunk.register_function(REPL_ENTRY.to_string(), 0),
+        };
+        let block = parser::Block {
+            statements,
+            expression: entry.expression,
+            line: entry.line,
+        };
+        self.compile_function(
+            number,
+            REPL_ENTRY.to_string(),
+            Vec::new(),
+            block,
+            entry.line,
+        )?;
+        Ok(self.chunk.function_locations[number])
     }
 
     fn compile_statement(&mut self, statement: parser::Statement, top_level: bool) -> Result<()> {
         match statement {
             parser::Statement::LetStatement(v) => self.compile_let_statement(v, top_level),
+            parser::Statement::DestructuringLetStatement(d) => {
+                self.compile_destructuring_let_statement(d, top_level)
+            }
             parser::Statement::ConstStatement(c) => self.compile_const_statement(c),
             parser::Statement::PrintStatement(p) => self.compile_print_statement(p),
             parser::Statement::ExpressionStatement(e) => self.compile_expression_statement(e),
             parser::Statement::FnStatement(f) => self.compile_fn_statement(f, top_level),
+            parser::Statement::StructStatement(_) => Ok(()),
+            parser::Statement::EnumStatement(_) => Ok(()),
+            parser::Statement::ImplStatement(i) => self.compile_impl_statement(i),
+            // Imports are all done up front by compile_program.
+            parser::Statement::ImportStatement(_) => Ok(()),
+        }
+    }
+
+    fn compile_import(&mut self, import: &parser::ImportStatement) -> Result<()> {
+        let path = match self.find_module(&import.path) {
+            Some(path) => path,
+            None => {
+                return Err(CompilerError(format!(
+                    "Could not find module {} (line {})",
+                    import.path, import.line
+                )))
+            }
+        };
+        if let Some(start) = self.loader.loading.iter().position(|p| *p == path) {
+            let cycle = self.loader.loading[start..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|p| p.file_name().unwrap_or_default().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Err(CompilerError(format!(
+                "Import cycle: {} (line {})",
+                cycle, import.line
+            )));
+        }
+        if self.imports.contains_key(&import.name) {
+            return Err(CompilerError(format!(
+                "Module {} is already imported (line {})",
+                import.name, import.line
+            )));
+        }
+        let module = match self.loader.modules.get(&path) {
+            Some(module) => module.clone(),
+            None => self.compile_module(&path, &import.name)?,
+        };
+        self.imports.insert(import.name.clone(), module);
+
+        Ok(())
+    }
+
+    // Look for a module relative to the importing file, then in each
+    // directory of the search path.
+    fn find_module(&self, name: &str) -> Option<PathBuf> {
+        let mut file = PathBuf::from(name);
+        if file.extension().is_none() {
+            file.set_extension("nlx");
+        }
+        let local = match &self.directory {
+            Some(directory) => directory.join(&file),
+            None => file.clone(),
+        };
+        std::iter::once(local)
+            .chain(self.loader.search_path.iter().map(|d| d.join(&file)))
+            .find(|p| p.is_file())
+            .and_then(|p| p.canonicalize().ok())
+    }
+
+    // Compile a module into this compiler's chunk with a compiler of its own,
+    // so it gets its own top level scope and imports.
+    fn compile_module(&mut self, path: &Path, name: &str) -> Result<Module> {
+        let source = std::fs::read_to_string(path).map_err(|e| {
+            CompilerError(format!("Unable to read module {}: {}", path.display(), e))
+        })?;
+        let ast = parser::parse(&source).map_err(|e| module_error(path, e))?;
+
+        let mut prefix = format!("{}.", name);
+        let mut n = 1;
+        while self.loader.modules.values().any(|m| m.prefix == prefix) {
+            n += 1;
+            prefix = format!("{}#{}.", name, n);
+        }
+
+        let mut compiler = Compiler::new();
+        compiler.chunk = std::mem::take(&mut self.chunk);
+        compiler.loader = std::mem::take(&mut self.loader);
+        compiler.natives = self.natives.clone();
+        compiler.wide_jumps = self.wide_jumps;
+        compiler.module_prefix = prefix.clone();
+        compiler.directory = path.parent().map(Path::to_path_buf);
+        compiler.loader.loading.push(path.to_path_buf());
+        let result = compiler.compile_program(ast);
+        compiler.loader.loading.pop();
+        self.chunk = compiler.chunk;
+        self.loader = compiler.loader;
+        self.jump_overflow |= compiler.jump_overflow;
+        result.map_err(|e| module_error(path, e))?;
+
+        let module = Module {
+            prefix,
+            consts: compiler.environments.swap_remove(0).consts,
+        };
+        self.loader
+            .modules
+            .insert(path.to_path_buf(), module.clone());
+        Ok(module)
+    }
+
+    fn compile_impl_statement(&mut self, impl_statement: parser::ImplStatement) -> Result<()> {
+        let qualified = self.qualified_name(&impl_statement.name);
+        let struct_number = match self.chunk.struct_names.get(&qualified) {
+            Some(n) => *n as usize,
+            None => {
+                return Err(CompilerError(format!(
+                    "impl for unknown struct: {} (line {})",
+                    impl_statement.name, impl_statement.line
+                )))
+            }
+        };
+        for method in impl_statement.methods {
+            let name = format!("{}::{}", qualified, method.name);
+            if method.args.is_empty() {
+                return Err(CompilerError(format!(
+                    "Method {} must take self as its first argument",
+                    name
+                )));
+            }
+            if self.chunk.structs[struct_number]
+                .methods
+                .contains_key(&method.name)
+            {
+                return Err(CompilerError(format!("Duplicate method: {}", name)));
+            }
+            // A method call pushes the receiver after the other arguments, so
+            // bind self last.
+            let mut args = method.args;
+            let receiver = args.remove(0);
+            args.push(receiver);
+            let arity = arg_count(args.len(), method.line)?;
+            let number = self.chunk.register_function(name.clone(), arity);
+            self.chunk.structs[struct_number]
+                .methods
+                .insert(method.name, number);
+            self.compile_function(number, name, args, method.block, method.line)?;
+        }
+
+        Ok(())
+    }
+
+    // Struct and enum names are prefixed like functions, so modules can use
+    // the same names.
+    fn qualified_name(&self, name: &str) -> String {
+        format!("{}{}", self.module_prefix, name)
+    }
+
+    fn declare_struct(&mut self, struct_statement: &parser::StructStatement) -> Result<()> {
+        let qualified = self.qualified_name(&struct_statement.name);
+        if self.chunk.struct_names.contains_key(&qualified) {
+            return Err(CompilerError(format!(
+                "Duplicate struct definition: {} (line {})",
+                struct_statement.name, struct_statement.line
+            )));
+        }
+        for (i, field) in struct_statement.fields.iter().enumerate() {
+            if struct_statement.fields[..i].contains(field) {
+                return Err(CompilerError(format!(
+                    "Duplicate field {} in struct {}",
+                    field, struct_statement.name
+                )));
+            }
+        }
+        if self.chunk.structs.len() > u8::MAX as usize {
+            return Err(CompilerError(format!(
+                "Too many structs (line {})",
+                struct_statement.line
+            )));
+        }
+        if struct_statement.fields.len() > u8::MAX as usize + 1 {
+            return Err(CompilerError(format!(
+                "Too many fields in struct {}",
+                struct_statement.name
+            )));
+        }
+        // With the checks above, this can only fail for lack of field numbers.
+        match self
+            .chunk
+            .register_struct(qualified, struct_statement.fields.clone())
+        {
+            Some(_) => Ok(()),
+            None => Err(too_many_fields(struct_statement.line)),
+        }
+    }
+
+    fn declare_enum(&mut self, enum_statement: &parser::EnumStatement) -> Result<()> {
+        let qualified = self.qualified_name(&enum_statement.name);
+        if self.chunk.enum_names.contains_key(&qualified) {
+            return Err(CompilerError(format!(
+                "Duplicate enum definition: {} (line {})",
+                enum_statement.name, enum_statement.line
+            )));
+        }
+        let mut variants: Vec<chunk::VariantDefinition> = Vec::new();
+        for v in &enum_statement.variants {
+            if variants.iter().any(|d| d.name == v.name) {
+                return Err(CompilerError(format!(
+                    "Duplicate variant {} in enum {}",
+                    v.name, enum_statement.name
+                )));
+            }
+            if v.fields.len() > u8::MAX as usize {
+                return Err(CompilerError(format!(
+                    "Too many values in variant {}::{}",
+                    enum_statement.name, v.name
+                )));
+            }
+            variants.push(chunk::VariantDefinition {
+                name: v.name.clone(),
+                arity: v.fields.len() as u8,
+            });
+        }
+        match self.chunk.register_enum(qualified, variants) {
+            Some(_) => Ok(()),
+            None => Err(CompilerError(format!(
+                "Too many enums or variants in enum {} (line {})",
+                enum_statement.name, enum_statement.line
+            ))),
         }
     }
 
-    fn bind_local(&mut self, name: String) -> u8 {
+    // Slots are reused once the environment binding them is popped, so a
+    // function needs as many as are in scope at once.
+    fn bind_local(&mut self, name: String) -> Result<usize> {
         let current_env = self.environments.last_mut().unwrap();
-        current_env.locals.insert(name, current_env.next_local);
+        let local = current_env.next_local;
+        if local >= MAX_LOCALS {
+            return Err(CompilerError(format!(
+                "Too many local variables in function {}",
+                self.function_name
+            )));
+        }
+        current_env.locals.insert(name, local);
         current_env.next_local += 1;
-        self.max_local += 1;
-        current_env.next_local - 1
+        self.max_local = std::cmp::max(self.max_local, current_env.next_local);
+        Ok(local)
     }
 
-    fn bind_const(&mut self, name: String, value: value::Value) -> u8 {
+    fn bind_const(&mut self, name: String, value: value::Value) -> usize {
         let c = self.chunk.add_constant(value);
         let current_env = self.environments.last_mut().unwrap();
         current_env.consts.insert(name, c);
@@ -203,17 +961,17 @@ impl Compiler {
             } else {
                 value::Value::Nil
             };
-            self.chunk.register_global(&name, value);
+            self.chunk
+                .register_global(&format!("{}{}", self.module_prefix, name), value);
         } else {
             let mut need_to_assign = false;
             if let Some(expression) = initializer {
                 self.compile_expression(expression)?;
                 need_to_assign = true;
             }
-            let local_number = self.bind_local(name);
+            let local_number = self.bind_local(name)?;
             if need_to_assign {
-                self.chunk.write_chunk(OpCode::AssignLocal as u8, line);
-                self.chunk.write_chunk(local_number, line);
+                self.emit_local_op(OpCode::AssignLocal, local_number, line);
                 self.adjust_stack_usage(-1);
             }
         }
@@ -221,6 +979,21 @@ impl Compiler {
         Ok(())
     }
 
+    fn compile_destructuring_let_statement(
+        &mut self,
+        let_statement: parser::DestructuringLetStatement,
+        top_level: bool,
+    ) -> Result<()> {
+        if top_level {
+            return Err(CompilerError(format!(
+                "Destructuring let is only allowed inside functions (line {})",
+                let_statement.line
+            )));
+        }
+        self.compile_expression(let_statement.initializer)?;
+        self.compile_destructure(let_statement.pattern, let_statement.line)
+    }
+
     fn compile_const_statement(&mut self, const_statement: parser::ConstStatement) -> Result<()> {
         let value = self.evaluate(const_statement.initializer)?;
         self.bind_const(const_statement.name, value);
@@ -252,39 +1025,106 @@ impl Compiler {
         top_level: bool,
     ) -> Result<()> {
         if !top_level {
-            let c = self.bind_const(fn_statement.name.clone(), value::Value::Callable(0));
-            self.chunk
-                .register_function(fn_statement.name.clone(), fn_statement.args.len() as u8);
-            self.deferred.push((fn_statement, c));
+            // Nested functions are closures, stored in a local. Bind it before
+            // compiling the body so the function can call itself.
+            let line = fn_statement.line;
+            let local_number = self.bind_local(fn_statement.name.clone())?;
+            let name = self.nested_function_name(&fn_statement.name);
+            self.compile_closure(name, fn_statement.args, fn_statement.block, line)?;
+            self.emit_local_op(OpCode::AssignLocal, local_number, line);
+            self.adjust_stack_usage(-1);
 
             Ok(())
         } else {
+            let name = format!("{}{}", self.module_prefix, fn_statement.name);
+            let arity = arg_count(fn_statement.args.len(), fn_statement.line)?;
+            let number = self.chunk.register_function(name.clone(), arity);
             self.bind_const(
                 fn_statement.name.clone(),
-                value::Value::Callable(self.chunk.code.len()),
+                value::Value::Callable(number as usize),
             );
-            self.chunk
-                .register_function(fn_statement.name.clone(), fn_statement.args.len() as u8);
-            self.max_local = 0;
-            self.pushed_this_fn = 0;
-            let locals_addr = self
-                .chunk
-                .start_function(&fn_statement.name, fn_statement.line);
-            for arg in fn_statement.args.into_iter().rev() {
-                let local_number = self.bind_local(arg);
-                self.chunk
-                    .write_chunk(OpCode::AssignLocal as u8, fn_statement.line);
-                self.chunk.write_chunk(local_number, fn_statement.line);
-            }
-            self.compile_block(fn_statement.block)?;
-            self.chunk
-                .write_chunk(OpCode::Return as u8, fn_statement.line);
-            self.chunk.code[locals_addr] = self.max_local;
+            self.compile_function(
+                number,
+                name,
+                fn_statement.args,
+                fn_statement.block,
+                fn_statement.line,
+            )?;
 
             Ok(())
         }
     }
 
+    // Qualify a nested function's name with its enclosing function, making
+    // sure it doesn't clash with an existing function of the same name.
+    fn nested_function_name(&self, name: &str) -> String {
+        let qualified = format!("{}::{}", self.function_name, name);
+        let mut unique = qualified.clone();
+        let mut n = 1;
+        while self.chunk.function_names.contains_key(&unique) {
+            n += 1;
+            unique = format!("{}#{}", qualified, n);
+        }
+        unique
+    }
+
+    fn compile_function(
+        &mut self,
+        number: usize,
+        name: String,
+        args: Vec<String>,
+        block: parser::Block,
+        line: usize,
+    ) -> Result<Vec<UpvalueDescriptor>> {
+        let locals_addr = self.chunk.start_function(number, line);
+        self.begin_function(name);
+        for arg in args.into_iter().rev() {
+            let local_number = self.bind_local(arg)?;
+            self.emit_local_op(OpCode::AssignLocal, local_number, line);
+        }
+        self.compile_block(block)?;
+        self.chunk.write_chunk(OpCode::Return as u8, line);
+        self.chunk.code[locals_addr] = (self.max_local & 0xFF) as u8;
+        self.chunk.code[locals_addr + 1] = (self.max_local >> 8) as u8;
+
+        Ok(self.end_function())
+    }
+
+    // Compile a function body inline (jumping over it) followed by a Closure
+    // instruction which captures its upvalues and pushes the closure.
+    fn compile_closure(
+        &mut self,
+        name: String,
+        args: Vec<String>,
+        block: parser::Block,
+        line: usize,
+    ) -> Result<()> {
+        let jump_address = self.emit_jump(OpCode::Jump, line);
+        let arity = arg_count(args.len(), line)?;
+        let number = self.chunk.register_function(name.clone(), arity);
+        let upvalues = self.compile_function(number, name, args, block, line)?;
+        let current_address = self.chunk.code.len();
+        self.insert_jump_address(jump_address, current_address);
+
+        self.emit_constant(value::Value::Callable(number as usize), line)?;
+        self.chunk.write_chunk(OpCode::Closure as u8, line);
+        if upvalues.len() > u8::MAX as usize {
+            return Err(CompilerError(format!(
+                "Too many captured variables in function {} (line {})",
+                self.chunk.function_name(number),
+                line
+            )));
+        }
+        self.chunk.write_chunk(upvalues.len() as u8, line);
+        for upvalue in upvalues {
+            self.chunk.write_chunk(upvalue.is_local as u8, line);
+            self.emit_short(upvalue.index, line);
+        }
+        self.adjust_stack_usage(1);
+
+        Ok(())
+    }
+
     fn compile_expression(&mut self, expression: parser::Expression) -> Result<()> {
         match expression {
             parser::Expression::Literal(l) => self.compile_literal(l),
@@ -308,29 +1148,95 @@ impl Compiler {
             parser::Expression::Return(r) => self.compile_return(r),
             parser::Expression::Continue(line) => self.compile_continue(line),
             parser::Expression::Break(line) => self.compile_break(line),
+            parser::Expression::Lambda(l) => self.compile_lambda(l),
+            parser::Expression::Field(f) => self.compile_field(f),
+            parser::Expression::StructLiteral(s) => self.compile_struct_literal(s),
+            parser::Expression::Path(p) => self.compile_enum_value(p, Vec::new()),
+            parser::Expression::Match(m) => self.compile_match(m),
+            parser::Expression::Interpolation(i) => self.compile_interpolation(i),
+            parser::Expression::Throw(t) => self.compile_throw(t),
+            parser::Expression::Try(t) => self.compile_try(t),
         }
     }
 
+    fn compile_lambda(&mut self, lambda: parser::Lambda) -> Result<()> {
+        let name = self.nested_function_name(&format!("<lambda:{}>", lambda.line));
+        self.compile_closure(name, lambda.args, lambda.block, lambda.line)
+    }
+
+    fn compile_throw(&mut self, throw: parser::Throw) -> Result<()> {
+        self.compile_expression(*throw.value)?;
+        self.chunk.write_chunk(OpCode::Throw as u8, throw.line);
+        // Throw never continues, so let the thrown value stand in for the
+        // expression's value in the stack usage.
+
+        Ok(())
+    }
+
+    // The handler pushed here records the VM state to unwind to. If anything
+    // in the try block throws, the VM restores that state, pushes the error
+    // and jumps to the catch block, where it's bound to the error name.
+    fn compile_try(&mut self, try_expression: parser::Try) -> Result<()> {
+        let line = try_expression.line;
+        let first_local = self.environments.last().unwrap().next_local;
+        let push_handler = self.jump_opcode(OpCode::PushHandler);
+        self.chunk.write_chunk(push_handler as u8, line);
+        self.emit_short(first_local, line);
+        let handler_address = self.emit_jump_offset(line);
+        self.loop_contexts.last_mut().unwrap().handlers += 1;
+        self.compile_block(try_expression.block)?;
+        self.loop_contexts.last_mut().unwrap().handlers -= 1;
+        self.chunk.write_chunk(OpCode::PopHandler as u8, line);
+        let end_address = self.emit_jump(OpCode::Jump, line);
+        let addr = self.chunk.code.len();
+        self.insert_jump_address(handler_address, addr);
+
+        self.push_environment();
+        let error_local = self.bind_local(try_expression.error_name)?;
+        self.emit_assign_local(error_local, line);
+        self.compile_block(try_expression.catch_block)?;
+        self.pop_environment(line);
+        let addr = self.chunk.code.len();
+        self.insert_jump_address(end_address, addr);
+
+        Ok(())
+    }
+
+    fn compile_interpolation(&mut self, interpolation: parser::Interpolation) -> Result<()> {
+        let line = interpolation.line;
+        let mut nparts = 0;
+        for p in interpolation.parts {
+            if nparts == MAX_INTERPOLATION_PARTS {
+                // The joined string becomes the first part of the next group.
+                self.emit_interpolate(nparts, line);
+                nparts = 1;
+            }
+            self.compile_expression(p)?;
+            nparts += 1;
+        }
+        self.emit_interpolate(nparts, line);
+
+        Ok(())
+    }
+
+    fn emit_interpolate(&mut self, nparts: usize, line: usize) {
+        self.chunk.write_chunk(OpCode::Interpolate as u8, line);
+        self.chunk.write_chunk(nparts as u8, line);
+        self.adjust_stack_usage(1 - nparts as i8);
+    }
+
     fn compile_literal(&mut self, literal: parser::Literal) -> Result<()> {
         match literal {
             parser::Literal::Number(n, line) => {
-                let c = self.chunk.add_constant(value::Value::Number(n));
-                self.chunk.write_chunk(OpCode::Constant as u8, line);
-                self.chunk.write_chunk(c, line);
+                self.emit_constant(value::Value::Number(n), line)?;
                 self.adjust_stack_usage(1);
             }
             parser::Literal::String(s, line) => {
-                let c = self.chunk.add_constant(value::Value::String(s));
-                self.chunk.write_chunk(OpCode::Constant as u8, line);
-                self.chunk.write_chunk(c, line);
+                self.emit_constant(value::Value::String(s), line)?;
                 self.adjust_stack_usage(1);
             }
             parser::Literal::Char(c, line) => {
-                let c = self
-                    .chunk
-                    .add_constant(value::Value::Number(c as u64 as f64));
-                self.chunk.write_chunk(OpCode::Constant as u8, line);
-                self.chunk.write_chunk(c, line);
+                self.emit_constant(value::Value::Number(c as u64 as f64), line)?;
                 self.adjust_stack_usage(1);
             }
             parser::Literal::False(line) => {
@@ -403,11 +1309,7 @@ impl Compiler {
         self.compile_expression(*binary.left)?;
         self.chunk.write_chunk(OpCode::Dup as u8, binary.line);
         self.adjust_stack_usage(1);
-        self.chunk
-            .write_chunk(OpCode::JumpIfFalse as u8, binary.line);
-        let jump_address = self.chunk.code.len();
-        self.chunk.write_chunk(0, binary.line);
-        self.chunk.write_chunk(0, binary.line);
+        let jump_address = self.emit_jump(OpCode::JumpIfFalse, binary.line);
         self.adjust_stack_usage(-1);
         self.chunk.write_chunk(OpCode::Pop as u8, binary.line);
         self.adjust_stack_usage(-1);
@@ -422,11 +1324,7 @@ impl Compiler {
         self.compile_expression(*binary.left)?;
         self.chunk.write_chunk(OpCode::Dup as u8, binary.line);
         self.adjust_stack_usage(1);
-        self.chunk
-            .write_chunk(OpCode::JumpIfTrue as u8, binary.line);
-        let jump_address = self.chunk.code.len();
-        self.chunk.write_chunk(0, binary.line);
-        self.chunk.write_chunk(0, binary.line);
+        let jump_address = self.emit_jump(OpCode::JumpIfTrue, binary.line);
         self.adjust_stack_usage(-1);
         self.chunk.write_chunk(OpCode::Pop as u8, binary.line);
         self.adjust_stack_usage(-1);
@@ -442,31 +1340,72 @@ impl Compiler {
     }
 
     fn compile_variable(&mut self, variable: parser::Variable) -> Result<()> {
-        match self.find_symbol(&variable.name) {
+        let symbol = self.find_symbol(&variable.name);
+        self.emit_load_symbol(symbol, variable.name, variable.line)
+    }
+
+    fn emit_load_symbol(
+        &mut self,
+        symbol: ResolvedSymbol,
+        name: String,
+        line: usize,
+    ) -> Result<()> {
+        match symbol {
             ResolvedSymbol::Local(number) => {
-                self.chunk
-                    .write_chunk(OpCode::LoadLocal as u8, variable.line);
-                self.chunk.write_chunk(number, variable.line);
+                self.emit_local_op(OpCode::LoadLocal, number, line);
+                self.adjust_stack_usage(1);
+            }
+            ResolvedSymbol::Upvalue(number) => {
+                self.chunk.write_chunk(OpCode::LoadUpvalue as u8, line);
+                self.chunk.write_chunk(number as u8, line);
                 self.adjust_stack_usage(1);
             }
             ResolvedSymbol::Const(number) => {
-                self.chunk
-                    .write_chunk(OpCode::Constant as u8, variable.line);
-                self.chunk.write_chunk(number, variable.line);
+                self.emit_load_constant(number, line)?;
                 self.adjust_stack_usage(1);
             }
-            ResolvedSymbol::Global => {
-                let c = self.chunk.add_constant(value::Value::String(variable.name));
-                self.chunk
-                    .write_chunk(OpCode::Constant as u8, variable.line);
-                self.chunk.write_chunk(c, variable.line);
-                self.chunk
-                    .write_chunk(OpCode::LoadGlobal as u8, variable.line);
+            ResolvedSymbol::Global(global) => {
+                self.emit_constant(value::Value::String(global), line)?;
+                self.chunk.write_chunk(OpCode::LoadGlobal as u8, line);
+            }
+            ResolvedSymbol::Undefined => {
+                return Err(CompilerError(format!("Undefined variable: {}", name)));
+            }
+        }
+        Ok(())
+    }
+
+    // Assign the value on top of the stack to symbol.
+    fn emit_assign_symbol(
+        &mut self,
+        symbol: ResolvedSymbol,
+        name: String,
+        line: usize,
+    ) -> Result<()> {
+        match symbol {
+            ResolvedSymbol::Local(local_number) => {
+                self.emit_local_op(OpCode::AssignLocal, local_number, line);
+                self.adjust_stack_usage(-1);
+            }
+            ResolvedSymbol::Upvalue(upvalue_number) => {
+                self.chunk.write_chunk(OpCode::AssignUpvalue as u8, line);
+                self.chunk.write_chunk(upvalue_number as u8, line);
+                self.adjust_stack_usage(-1);
+            }
+            ResolvedSymbol::Const(_) => {
+                return Err(CompilerError(format!(
+                    "Attempt to assign to const: {}",
+                    name
+                )));
+            }
+            ResolvedSymbol::Global(global) => {
+                self.emit_constant(value::Value::String(global), line)?;
+                self.chunk.write_chunk(OpCode::AssignGlobal as u8, line);
             }
             ResolvedSymbol::Undefined => {
                 return Err(CompilerError(format!(
-                    "Undefined variable: {}",
-                    variable.name
+                    "Assignment to undefined local: {}",
+                    name
                 )));
             }
         }
@@ -485,43 +1424,151 @@ impl Compiler {
                 self.adjust_stack_usage(1);
             }
         }
-        self.pop_environment();
+        self.pop_environment(block.line);
 
         Ok(())
     }
 
     fn compile_call(&mut self, call: parser::Call) -> Result<()> {
-        let nargs = call.args.len() as u8;
+        let callee = match *call.callee {
+            parser::Expression::Path(p) => return self.compile_enum_value(p, call.args),
+            parser::Expression::Variable(v) if v.name == "exit" => match self.find_symbol("exit") {
+                ResolvedSymbol::Undefined => return self.compile_exit(call.args, call.line),
+                _ => parser::Expression::Variable(v),
+            },
+            callee => callee,
+        };
+        let nargs = arg_count(call.args.len(), call.line)?;
         for e in call.args {
             self.compile_expression(e)?;
         }
-        self.compile_expression(*call.callee)?;
+        self.compile_expression(callee)?;
         self.chunk.write_chunk(OpCode::Call as u8, call.line);
+        self.chunk.write_chunk(nargs, call.line);
         self.adjust_stack_usage(-(nargs as i8));
 
         Ok(())
     }
 
+    // exit(n) is built in, unless the program defines its own exit.
+    fn compile_exit(&mut self, mut args: Vec<parser::Expression>, line: usize) -> Result<()> {
+        if args.len() != 1 {
+            return Err(CompilerError(format!(
+                "exit takes 1 argument but was given {} (line {})",
+                args.len(),
+                line
+            )));
+        }
+        self.compile_expression(args.remove(0))?;
+        self.chunk.write_chunk(OpCode::Exit as u8, line);
+
+        Ok(())
+    }
+
+    // Constants past the first 256 need the wide form of OP_CONSTANT.
+    fn emit_constant(&mut self, value: value::Value, line: usize) -> Result<()> {
+        let c = self.chunk.add_constant(value);
+        self.emit_load_constant(c, line)
+    }
+
+    fn emit_load_constant(&mut self, c: usize, line: usize) -> Result<()> {
+        if c <= u8::MAX as usize {
+            self.chunk.write_chunk(OpCode::Constant as u8, line);
+            self.chunk.write_chunk(c as u8, line);
+        } else if c <= MAX_LONG_OPERAND {
+            self.chunk.write_chunk(OpCode::ConstantLong as u8, line);
+            self.chunk.write_chunk((c & 0xFF) as u8, line);
+            self.chunk.write_chunk((c >> 8 & 0xFF) as u8, line);
+            self.chunk.write_chunk((c >> 16) as u8, line);
+        } else {
+            return Err(NotloxError::CompilerError(format!(
+                "Too many constants (line {})",
+                line
+            )));
+        }
+        Ok(())
+    }
+
+    // Jumps have 16 bit offsets unless wide_jumps is set. Forward jumps are
+    // written before their distance is known, so if any turns out too far
+    // the whole program is compiled again with wide jumps (see
+    // compile_with_wide_jumps).
+    fn jump_opcode(&self, op: OpCode) -> OpCode {
+        if !self.wide_jumps {
+            return op;
+        }
+        match op {
+            OpCode::Jump => OpCode::JumpLong,
+            OpCode::JumpIfFalse => OpCode::JumpIfFalseLong,
+            OpCode::JumpIfTrue => OpCode::JumpIfTrueLong,
+            OpCode::ForLoop => OpCode::ForLoopLong,
+            OpCode::PushHandler => OpCode::PushHandlerLong,
+            _ => op,
+        }
+    }
+
+    // Returns the address of the offset, for insert_jump_address.
+    fn emit_jump(&mut self, op: OpCode, line: usize) -> usize {
+        let op = self.jump_opcode(op);
+        self.chunk.write_chunk(op as u8, line);
+        self.emit_jump_offset(line)
+    }
+
+    fn emit_jump_offset(&mut self, line: usize) -> usize {
+        let address = self.chunk.code.len();
+        for _ in 0..self.jump_offset_size() {
+            self.chunk.write_chunk(0, line);
+        }
+        address
+    }
+
+    fn jump_offset_size(&self) -> usize {
+        if self.wide_jumps {
+            3
+        } else {
+            2
+        }
+    }
+
     fn insert_jump_address(&mut self, jump_target_address: usize, dest_address: usize) {
-        let addr = (dest_address as isize - jump_target_address as isize - 2) as i16;
-        self.chunk.code[jump_target_address] = (addr & 0xFF) as u8;
-        self.chunk.code[jump_target_address + 1] = (addr >> 8) as u8;
+        let size = self.jump_offset_size();
+        let addr = dest_address as isize - (jump_target_address + size) as isize;
+        let limit = 1 << (size * 8 - 1);
+        if addr < -limit || addr >= limit {
+            self.jump_overflow = true;
+            return;
+        }
+        for i in 0..size {
+            self.chunk.code[jump_target_address + i] = (addr >> (i * 8) & 0xFF) as u8;
+        }
+    }
+
+    fn compile_with_wide_jumps<T>(
+        &mut self,
+        compile: impl Fn(&mut Compiler) -> Result<T>,
+    ) -> Result<T> {
+        let start = self.clone();
+        let result = compile(self)?;
+        if !self.jump_overflow {
+            return Ok(result);
+        }
+        *self = start;
+        self.wide_jumps = true;
+        let result = compile(self)?;
+        if self.jump_overflow {
+            return Err(CompilerError(
+                "Program too large, jump out of range.".to_string(),
+            ));
+        }
+        Ok(result)
     }
 
     fn compile_if(&mut self, if_expression: parser::If) -> Result<()> {
         self.compile_expression(*if_expression.condition)?;
-        self.chunk
-            .write_chunk(OpCode::JumpIfFalse as u8, if_expression.line);
-        self.chunk.write_chunk(0, if_expression.line);
-        self.chunk.write_chunk(0, if_expression.line);
+        let jump_target_address = self.emit_jump(OpCode::JumpIfFalse, if_expression.line);
         self.adjust_stack_usage(-1);
-        let jump_target_address = self.chunk.code.len() - 2;
         self.compile_block(if_expression.then_block)?;
-        self.chunk
-            .write_chunk(OpCode::Jump as u8, if_expression.line);
-        self.chunk.write_chunk(0, if_expression.line);
-        self.chunk.write_chunk(0, if_expression.line);
-        let else_target_address = self.chunk.code.len() - 2;
+        let else_target_address = self.emit_jump(OpCode::Jump, if_expression.line);
         let addr = self.chunk.code.len();
         self.insert_jump_address(jump_target_address, addr);
         self.adjust_stack_usage(-1);
@@ -542,23 +1589,16 @@ impl Compiler {
     fn compile_while(&mut self, while_expression: parser::While) -> Result<()> {
         let while_start_address = self.chunk.code.len();
         self.compile_expression(*while_expression.condition)?;
-        self.chunk
-            .write_chunk(OpCode::JumpIfFalse as u8, while_expression.line);
-        self.chunk.write_chunk(0, while_expression.line);
-        self.chunk.write_chunk(0, while_expression.line);
+        let jump_target_address = self.emit_jump(OpCode::JumpIfFalse, while_expression.line);
         self.adjust_stack_usage(-1);
         self.push_loop_context(while_start_address, false);
-        let jump_target_address = self.chunk.code.len() - 2;
         self.compile_block(while_expression.block)?;
         self.chunk
             .write_chunk(OpCode::Pop as u8, while_expression.line);
         self.adjust_stack_usage(-1);
-        self.chunk
-            .write_chunk(OpCode::Jump as u8, while_expression.line);
-        self.chunk.write_chunk(0, while_expression.line);
-        self.chunk.write_chunk(0, while_expression.line);
+        let loop_jump = self.emit_jump(OpCode::Jump, while_expression.line);
         let current_address = self.chunk.code.len();
-        self.insert_jump_address(current_address - 2, while_start_address);
+        self.insert_jump_address(loop_jump, while_start_address);
         self.insert_jump_address(jump_target_address, current_address);
         self.pop_loop_context(current_address);
         self.chunk
@@ -570,49 +1610,38 @@ impl Compiler {
 
     fn compile_for(&mut self, for_expression: parser::For) -> Result<()> {
         self.compile_expression(*for_expression.range)?;
-        let for_local_n = self.bind_local("_for_loop_range".to_string());
-        self.chunk
-            .write_chunk(OpCode::AssignLocal as u8, for_expression.line);
-        self.chunk.write_chunk(for_local_n, for_expression.line);
-        self.chunk
-            .write_chunk(OpCode::LoadLocal as u8, for_expression.line);
-        self.chunk.write_chunk(for_local_n, for_expression.line);
+        let for_local_n = self.bind_local("_for_loop_range".to_string())?;
+        self.emit_local_op(OpCode::AssignLocal, for_local_n, for_expression.line);
+        self.emit_local_op(OpCode::LoadLocal, for_local_n, for_expression.line);
 
         let for_start_address = self.chunk.code.len();
-        self.chunk
-            .write_chunk(OpCode::ForLoop as u8, for_expression.line);
-        let local_n = self.bind_local(for_expression.variable);
-        self.chunk.write_chunk(local_n, for_expression.line);
-        self.chunk.write_chunk(0, for_expression.line);
-        self.chunk.write_chunk(0, for_expression.line);
-        let for_jump_target_address = self.chunk.code.len() - 2;
         self.push_loop_context(for_start_address, true);
-
-        if let Some(variable2) = for_expression.variable2 {
-            let local2_n = self.bind_local(variable2);
-            self.chunk
-                .write_chunk(OpCode::LoadLocal as u8, for_expression.line);
-            self.chunk.write_chunk(for_local_n, for_expression.line);
-            self.chunk
-                .write_chunk(OpCode::LoadLocal as u8, for_expression.line);
-            self.chunk.write_chunk(local_n, for_expression.line);
+        // The loop variable and pattern bindings get a fresh scope each
+        // iteration, so closures created in the body don't share them.
+        self.push_environment();
+        let for_loop = self.jump_opcode(OpCode::ForLoop);
+        self.chunk.write_chunk(for_loop as u8, for_expression.line);
+        let local_n = self.bind_local(for_expression.variable)?;
+        self.emit_short(local_n, for_expression.line);
+        let for_jump_target_address = self.emit_jump_offset(for_expression.line);
+
+        if let Some(pattern) = for_expression.value {
+            self.emit_local_op(OpCode::LoadLocal, for_local_n, for_expression.line);
+            self.emit_local_op(OpCode::LoadLocal, local_n, for_expression.line);
             self.chunk
                 .write_chunk(OpCode::Index as u8, for_expression.line);
-            self.chunk
-                .write_chunk(OpCode::AssignLocal as u8, for_expression.line);
-            self.chunk.write_chunk(local2_n as u8, for_expression.line);
+            self.adjust_stack_usage(1);
+            self.compile_destructure(pattern, for_expression.line)?;
         }
 
         self.compile_block(for_expression.block)?;
         self.chunk
             .write_chunk(OpCode::Pop as u8, for_expression.line);
         self.adjust_stack_usage(-1);
-        self.chunk
-            .write_chunk(OpCode::Jump as u8, for_expression.line);
-        self.chunk.write_chunk(0, for_expression.line);
-        self.chunk.write_chunk(0, for_expression.line);
+        self.pop_environment(for_expression.line);
+        let loop_jump = self.emit_jump(OpCode::Jump, for_expression.line);
         let current_address = self.chunk.code.len();
-        self.insert_jump_address(current_address - 2, for_start_address);
+        self.insert_jump_address(loop_jump, for_start_address);
         self.insert_jump_address(for_jump_target_address, current_address);
         self.chunk
             .write_chunk(OpCode::PushNil as u8, for_expression.line);
@@ -628,12 +1657,9 @@ impl Compiler {
         self.chunk
             .write_chunk(OpCode::Pop as u8, loop_expression.line);
         self.adjust_stack_usage(-1);
-        self.chunk
-            .write_chunk(OpCode::Jump as u8, loop_expression.line);
-        self.chunk.write_chunk(0, loop_expression.line);
-        self.chunk.write_chunk(0, loop_expression.line);
+        let loop_jump = self.emit_jump(OpCode::Jump, loop_expression.line);
         let current_address = self.chunk.code.len();
-        self.insert_jump_address(current_address - 2, loop_start_address);
+        self.insert_jump_address(loop_jump, loop_start_address);
         self.pop_loop_context(current_address);
         self.chunk
             .write_chunk(OpCode::PushNil as u8, loop_expression.line);
@@ -646,34 +1672,8 @@ impl Compiler {
         match assignment.lvalue {
             parser::LValue::Variable(v) => {
                 self.compile_expression(*assignment.value)?;
-                match self.find_symbol(&v.name) {
-                    ResolvedSymbol::Local(local_number) => {
-                        self.chunk
-                            .write_chunk(OpCode::AssignLocal as u8, assignment.line);
-                        self.chunk.write_chunk(local_number, assignment.line);
-                        self.adjust_stack_usage(-1);
-                    }
-                    ResolvedSymbol::Const(_) => {
-                        return Err(CompilerError(format!(
-                            "Attempt to assign to const: {}",
-                            v.name
-                        )));
-                    }
-                    ResolvedSymbol::Global => {
-                        let c = self.chunk.add_constant(value::Value::String(v.name));
-                        self.chunk
-                            .write_chunk(OpCode::Constant as u8, assignment.line);
-                        self.chunk.write_chunk(c, assignment.line);
-                        self.chunk
-                            .write_chunk(OpCode::AssignGlobal as u8, assignment.line);
-                    }
-                    ResolvedSymbol::Undefined => {
-                        return Err(CompilerError(format!(
-                            "Assignment to undefined local: {}",
-                            v.name
-                        )));
-                    }
-                }
+                let symbol = self.find_symbol(&v.name);
+                self.emit_assign_symbol(symbol, v.name, assignment.line)?;
             }
             parser::LValue::Index(i) => {
                 self.compile_expression(*i.indexer)?;
@@ -683,6 +1683,32 @@ impl Compiler {
                     .write_chunk(OpCode::IndexAssign as u8, assignment.line);
                 self.adjust_stack_usage(-3);
             }
+            parser::LValue::Field(f) => {
+                match self.find_module_member(&f.object, &f.name, f.line)? {
+                    Some(symbol) => {
+                        self.compile_expression(*assignment.value)?;
+                        self.emit_assign_symbol(symbol, f.name, assignment.line)?;
+                    }
+                    None => match self.chunk.field_numbers.get(&f.name).cloned() {
+                        Some(field_number) => {
+                            self.compile_expression(*f.object)?;
+                            self.compile_expression(*assignment.value)?;
+                            self.chunk
+                                .write_chunk(OpCode::SetField as u8, assignment.line);
+                            self.chunk.write_chunk(field_number, assignment.line);
+                            self.adjust_stack_usage(-2);
+                        }
+                        None => {
+                            self.compile_expression(*f.object)?;
+                            self.compile_literal(parser::Literal::String(f.name, f.line))?;
+                            self.compile_expression(*assignment.value)?;
+                            self.chunk
+                                .write_chunk(OpCode::IndexAssign as u8, assignment.line);
+                            self.adjust_stack_usage(-3);
+                        }
+                    },
+                }
+            }
         }
         self.chunk
             .write_chunk(OpCode::PushNil as u8, assignment.line);
@@ -710,6 +1736,7 @@ impl Compiler {
         let lvalue = Box::new(match compound_assignment.lvalue.clone() {
             parser::LValue::Variable(v) => parser::Expression::Variable(v),
             parser::LValue::Index(i) => parser::Expression::Index(i),
+            parser::LValue::Field(f) => parser::Expression::Field(f),
         });
         self.compile_assignment(parser::Assignment {
             lvalue: compound_assignment.lvalue,
@@ -734,6 +1761,435 @@ impl Compiler {
         Ok(())
     }
 
+    // Fields declared by some struct compile to field number lookups, which
+    // also work on maps. Other names are just map indexing, and fail on
+    // instances at runtime.
+    fn compile_field(&mut self, field: parser::Field) -> Result<()> {
+        if let Some(symbol) = self.find_module_member(&field.object, &field.name, field.line)? {
+            return self.emit_load_symbol(symbol, field.name, field.line);
+        }
+        match self.chunk.field_numbers.get(&field.name).cloned() {
+            Some(field_number) => {
+                self.compile_expression(*field.object)?;
+                self.chunk.write_chunk(OpCode::GetField as u8, field.line);
+                self.chunk.write_chunk(field_number, field.line);
+            }
+            None => {
+                self.compile_expression(*field.object)?;
+                self.compile_literal(parser::Literal::String(field.name, field.line))?;
+                self.chunk.write_chunk(OpCode::Index as u8, field.line);
+                self.adjust_stack_usage(-1);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile_struct_literal(&mut self, struct_literal: parser::StructLiteral) -> Result<()> {
+        let qualified = self.qualified_name(&struct_literal.name);
+        let struct_number = match self.chunk.struct_names.get(&qualified) {
+            Some(n) => *n,
+            None => {
+                return Err(CompilerError(format!(
+                    "Unknown struct: {}",
+                    struct_literal.name
+                )))
+            }
+        };
+        let definition = &self.chunk.structs[struct_number as usize];
+        let mut slots = Vec::new();
+        for i in &struct_literal.initializers {
+            let slot = match self.chunk.field_numbers.get(&i.name) {
+                Some(n) => definition.slot(*n as usize),
+                None => None,
+            };
+            match slot {
+                Some(slot) if !slots.contains(&slot) => slots.push(slot),
+                Some(_) => {
+                    return Err(CompilerError(format!(
+                        "Field {} initialized twice in {} literal",
+                        i.name, struct_literal.name
+                    )))
+                }
+                None => {
+                    return Err(CompilerError(format!(
+                        "Struct {} has no field {}",
+                        struct_literal.name, i.name
+                    )))
+                }
+            }
+        }
+        if slots.len() != definition.fields.len() {
+            let missing: Vec<_> = definition
+                .fields
+                .iter()
+                .enumerate()
+                .filter(|(n, _)| !slots.contains(&(*n as u8)))
+                .map(|(_, f)| f.as_str())
+                .collect();
+            return Err(CompilerError(format!(
+                "Missing fields in {} literal: {}",
+                struct_literal.name,
+                missing.join(", ")
+            )));
+        }
+
+        self.chunk
+            .write_chunk(OpCode::NewInstance as u8, struct_literal.line);
+        self.chunk.write_chunk(struct_number, struct_literal.line);
+        self.adjust_stack_usage(1);
+        for (i, slot) in struct_literal.initializers.into_iter().zip(slots) {
+            self.compile_expression(*i.value)?;
+            self.chunk.write_chunk(OpCode::InitField as u8, i.line);
+            self.chunk.write_chunk(slot, i.line);
+            self.adjust_stack_usage(-1);
+        }
+
+        Ok(())
+    }
+
+    fn resolve_variant(&self, path: &parser::Path) -> Result<(u8, u8)> {
+        let enum_number = match self.chunk.enum_names.get(&self.qualified_name(&path.base)) {
+            Some(n) => *n,
+            None => {
+                return Err(CompilerError(format!(
+                    "Unknown enum: {} (line {})",
+                    path.base, path.line
+                )))
+            }
+        };
+        match self.chunk.enums[enum_number as usize].variant(&path.name) {
+            Some(v) => Ok((enum_number, v)),
+            None => Err(CompilerError(format!(
+                "Enum {} has no variant {} (line {})",
+                path.base, path.name, path.line
+            ))),
+        }
+    }
+
+    fn check_variant_arity(
+        &self,
+        path: &parser::Path,
+        enum_number: u8,
+        variant: u8,
+        n: usize,
+    ) -> Result<()> {
+        let arity = self.chunk.enums[enum_number as usize].variants[variant as usize].arity;
+        if arity as usize != n {
+            return Err(CompilerError(format!(
+                "Variant {}::{} has {} values but {} were given (line {})",
+                path.base, path.name, arity, n, path.line
+            )));
+        }
+        Ok(())
+    }
+
+    fn compile_enum_value(
+        &mut self,
+        path: parser::Path,
+        args: Vec<parser::Expression>,
+    ) -> Result<()> {
+        let (enum_number, variant) = self.resolve_variant(&path)?;
+        self.check_variant_arity(&path, enum_number, variant, args.len())?;
+        let nargs = args.len() as i8;
+        for e in args {
+            self.compile_expression(e)?;
+        }
+        self.chunk.write_chunk(OpCode::NewEnum as u8, path.line);
+        self.chunk.write_chunk(enum_number, path.line);
+        self.chunk.write_chunk(variant, path.line);
+        self.adjust_stack_usage(1 - nargs);
+
+        Ok(())
+    }
+
+    // The matched value is stored in a hidden local. Each arm tests its
+    // pattern against it, jumping to the next arm on the first failed test,
+    // and binds any names in a fresh environment for the arm body.
+    fn compile_match(&mut self, match_expression: parser::Match) -> Result<()> {
+        self.check_exhaustive(&match_expression)?;
+        let line = match_expression.line;
+        self.push_environment();
+        self.compile_expression(*match_expression.value)?;
+        let value_local = self.bind_local("<match>".to_string())?;
+        self.emit_assign_local(value_local, line);
+
+        let mut end_jumps = Vec::new();
+        for arm in match_expression.arms {
+            let mut fail_jumps = Vec::new();
+            self.push_environment();
+            self.compile_pattern(arm.pattern, value_local, &mut fail_jumps)?;
+            self.compile_expression(*arm.body)?;
+            self.pop_environment(arm.line);
+            let jump = self.emit_jump(OpCode::Jump, arm.line);
+            end_jumps.push(jump);
+            self.adjust_stack_usage(-1);
+            let addr = self.chunk.code.len();
+            for f in fail_jumps {
+                self.insert_jump_address(f.jump, addr);
+            }
+        }
+        self.emit_local_op(OpCode::LoadLocal, value_local, line);
+        self.chunk.write_chunk(OpCode::NoMatch as u8, line);
+        let addr = self.chunk.code.len();
+        for j in end_jumps {
+            self.insert_jump_address(j, addr);
+        }
+        self.adjust_stack_usage(1);
+        self.pop_environment(line);
+
+        Ok(())
+    }
+
+    // Matches where every arm is a variant of the same enum must cover all its
+    // variants. Anything else falls back to a runtime error if no arm matches.
+    fn check_exhaustive(&self, match_expression: &parser::Match) -> Result<()> {
+        let mut enum_number = None;
+        let mut covered = Vec::new();
+        for arm in &match_expression.arms {
+            match &arm.pattern {
+                parser::Pattern::Wildcard | parser::Pattern::Binding(..) => return Ok(()),
+                parser::Pattern::Variant(v) => {
+                    let (e, variant) = self.resolve_variant(&v.path)?;
+                    if enum_number.get_or_insert(e) != &e {
+                        return Ok(());
+                    }
+                    if v.patterns.iter().all(is_irrefutable) {
+                        covered.push(variant);
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+        if let Some(e) = enum_number {
+            let definition = &self.chunk.enums[e as usize];
+            let missing: Vec<_> = definition
+                .variants
+                .iter()
+                .enumerate()
+                .filter(|(n, _)| !covered.contains(&(*n as u8)))
+                .map(|(_, v)| format!("{}::{}", definition.name, v.name))
+                .collect();
+            if !missing.is_empty() {
+                return Err(CompilerError(format!(
+                    "Non-exhaustive match, missing {} (line {})",
+                    missing.join(", "),
+                    match_expression.line
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Emit tests of a pattern against the value in local slot source.
+    fn compile_pattern(
+        &mut self,
+        pattern: parser::Pattern,
+        source: usize,
+        fail_jumps: &mut Vec<MatchFailure>,
+    ) -> Result<()> {
+        match pattern {
+            parser::Pattern::Wildcard => {}
+            parser::Pattern::Binding(name, line) => {
+                self.emit_load_local(source, line);
+                let local = self.bind_local(name)?;
+                self.emit_assign_local(local, line);
+            }
+            parser::Pattern::Literal(l) => {
+                let line = literal_line(&l);
+                let expected = Expected::Value(describe_literal(&l));
+                self.emit_load_local(source, line);
+                self.compile_literal(l)?;
+                self.chunk.write_chunk(OpCode::TestEqual as u8, line);
+                self.adjust_stack_usage(-1);
+                self.emit_match_jump(line, source, expected, fail_jumps);
+            }
+            parser::Pattern::Range(low, high, line) => {
+                let expected = Expected::Value(format!("number in {}..{}", low, high));
+                self.emit_load_local(source, line);
+                self.emit_constant(value::Value::Range(low, high), line)?;
+                self.chunk.write_chunk(OpCode::MatchRange as u8, line);
+                self.emit_match_jump(line, source, expected, fail_jumps);
+            }
+            parser::Pattern::Array(array) => {
+                let line = array.line;
+                let length = array.elements.len() as u8;
+                self.emit_load_local(source, line);
+                let expected = if array.rest.is_some() {
+                    self.chunk
+                        .write_chunk(OpCode::MatchArrayAtLeast as u8, line);
+                    Expected::ArrayAtLeast(length)
+                } else {
+                    self.chunk.write_chunk(OpCode::MatchArray as u8, line);
+                    Expected::Array(length)
+                };
+                self.chunk.write_chunk(length, line);
+                self.emit_match_jump(line, source, expected, fail_jumps);
+                for (i, p) in array.elements.into_iter().enumerate() {
+                    if let parser::Pattern::Wildcard = p {
+                        continue;
+                    }
+                    self.emit_load_local(source, line);
+                    self.emit_constant(value::Value::Number(i as f64), line)?;
+                    self.chunk.write_chunk(OpCode::Index as u8, line);
+                    self.compile_subpattern(p, line, fail_jumps)?;
+                }
+                match array.rest.map(|r| *r) {
+                    None | Some(parser::Pattern::Wildcard) => {}
+                    Some(rest) => {
+                        self.emit_load_local(source, line);
+                        self.chunk.write_chunk(OpCode::ArrayRest as u8, line);
+                        self.chunk.write_chunk(length, line);
+                        self.compile_subpattern(rest, line, fail_jumps)?;
+                    }
+                }
+            }
+            parser::Pattern::Map(keys, line) => {
+                for k in keys {
+                    let expected = Expected::Key(k.key.clone());
+                    let c = self.chunk.add_constant(value::Value::String(k.key));
+                    self.emit_load_local(source, line);
+                    self.emit_load_constant(c, line)?;
+                    self.chunk.write_chunk(OpCode::MatchKey as u8, line);
+                    self.emit_match_jump(line, source, expected, fail_jumps);
+                    if let parser::Pattern::Wildcard = k.pattern {
+                        continue;
+                    }
+                    self.emit_load_local(source, line);
+                    self.emit_load_constant(c, line)?;
+                    self.chunk.write_chunk(OpCode::GetKey as u8, line);
+                    self.compile_subpattern(k.pattern, line, fail_jumps)?;
+                }
+            }
+            parser::Pattern::Variant(v) => {
+                let line = v.path.line;
+                let (enum_number, variant) = self.resolve_variant(&v.path)?;
+                self.check_variant_arity(&v.path, enum_number, variant, v.patterns.len())?;
+                let expected = Expected::Value(format!("{}::{}", v.path.base, v.path.name));
+                self.emit_load_local(source, line);
+                self.chunk.write_chunk(OpCode::MatchVariant as u8, line);
+                self.chunk.write_chunk(enum_number, line);
+                self.chunk.write_chunk(variant, line);
+                self.emit_match_jump(line, source, expected, fail_jumps);
+                for (i, p) in v.patterns.into_iter().enumerate() {
+                    if let parser::Pattern::Wildcard = p {
+                        continue;
+                    }
+                    self.emit_load_local(source, line);
+                    self.chunk.write_chunk(OpCode::VariantPayload as u8, line);
+                    self.chunk.write_chunk(i as u8, line);
+                    self.compile_subpattern(p, line, fail_jumps)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Match a pattern against the value on top of the stack, consuming it.
+    fn compile_subpattern(
+        &mut self,
+        pattern: parser::Pattern,
+        line: usize,
+        fail_jumps: &mut Vec<MatchFailure>,
+    ) -> Result<()> {
+        if let parser::Pattern::Binding(name, _) = pattern {
+            let local = self.bind_local(name)?;
+            self.emit_assign_local(local, line);
+            return Ok(());
+        }
+        let local = self.bind_local("<match>".to_string())?;
+        self.emit_assign_local(local, line);
+        self.compile_pattern(pattern, local, fail_jumps)
+    }
+
+    // Destructure the value on top of the stack into the current environment,
+    // with a runtime error saying which part didn't have the right shape.
+    fn compile_destructure(&mut self, pattern: parser::Pattern, line: usize) -> Result<()> {
+        if let parser::Pattern::Binding(name, _) = pattern {
+            let local = self.bind_local(name)?;
+            self.emit_assign_local(local, line);
+            return Ok(());
+        }
+        let source = self.bind_local("<let>".to_string())?;
+        self.emit_assign_local(source, line);
+        let mut fail_jumps = Vec::new();
+        self.compile_pattern(pattern, source, &mut fail_jumps)?;
+        if fail_jumps.is_empty() {
+            return Ok(());
+        }
+
+        let end_jump = self.emit_jump(OpCode::Jump, line);
+        for f in fail_jumps {
+            let addr = self.chunk.code.len();
+            self.insert_jump_address(f.jump, addr);
+            self.emit_local_op(OpCode::LoadLocal, f.source, line);
+            let (kind, operand) = match f.expected {
+                Expected::Array(n) => (chunk::EXPECTED_ARRAY, value::Value::Number(n as f64)),
+                Expected::ArrayAtLeast(n) => (
+                    chunk::EXPECTED_ARRAY_AT_LEAST,
+                    value::Value::Number(n as f64),
+                ),
+                Expected::Key(k) => (chunk::EXPECTED_KEY, value::Value::String(k)),
+                Expected::Value(d) => (chunk::EXPECTED_VALUE, value::Value::String(d)),
+            };
+            self.emit_constant(operand, line)?;
+            self.chunk.write_chunk(OpCode::BadDestructure as u8, line);
+            self.chunk.write_chunk(kind, line);
+        }
+        let addr = self.chunk.code.len();
+        self.insert_jump_address(end_jump, addr);
+
+        Ok(())
+    }
+
+    fn emit_load_local(&mut self, local: usize, line: usize) {
+        self.emit_local_op(OpCode::LoadLocal, local, line);
+        self.adjust_stack_usage(1);
+    }
+
+    fn emit_assign_local(&mut self, local: usize, line: usize) {
+        self.emit_local_op(OpCode::AssignLocal, local, line);
+        self.adjust_stack_usage(-1);
+    }
+
+    // Locals past the first 256 need the wide forms of LoadLocal and
+    // AssignLocal.
+    fn emit_local_op(&mut self, op: OpCode, local: usize, line: usize) {
+        if local <= u8::MAX as usize {
+            self.chunk.write_chunk(op as u8, line);
+            self.chunk.write_chunk(local as u8, line);
+        } else {
+            let op = match op {
+                OpCode::LoadLocal => OpCode::LoadLocalLong,
+                _ => OpCode::AssignLocalLong,
+            };
+            self.chunk.write_chunk(op as u8, line);
+            self.emit_short(local, line);
+        }
+    }
+
+    fn emit_short(&mut self, n: usize, line: usize) {
+        self.chunk.write_chunk((n & 0xFF) as u8, line);
+        self.chunk.write_chunk((n >> 8) as u8, line);
+    }
+
+    fn emit_match_jump(
+        &mut self,
+        line: usize,
+        source: usize,
+        expected: Expected,
+        fail_jumps: &mut Vec<MatchFailure>,
+    ) {
+        let jump = self.emit_jump(OpCode::JumpIfFalse, line);
+        self.adjust_stack_usage(-1);
+        fail_jumps.push(MatchFailure {
+            jump,
+            source,
+            expected,
+        });
+    }
+
     fn compile_array(&mut self, array: parser::Array) -> Result<()> {
         self.chunk.write_chunk(OpCode::NewArray as u8, array.line);
         self.adjust_stack_usage(1);
@@ -752,9 +2208,7 @@ impl Compiler {
         for i in map.initializers {
             match i.key {
                 parser::MapLHS::Name(s) => {
-                    let c = self.chunk.add_constant(value::Value::String(s));
-                    self.chunk.write_chunk(OpCode::Constant as u8, map.line);
-                    self.chunk.write_chunk(c, map.line);
+                    self.emit_constant(value::Value::String(s), map.line)?;
                     self.adjust_stack_usage(1);
                 }
                 parser::MapLHS::Expression(e) => {
@@ -772,20 +2226,16 @@ impl Compiler {
     }
 
     fn compile_builtin_call(&mut self, builtin_call: parser::BuiltinCall) -> Result<()> {
-        let nargs = builtin_call.args.len() as u8;
+        let nargs = arg_count(builtin_call.args.len(), builtin_call.line)?;
         for e in builtin_call.args {
             self.compile_expression(e)?;
         }
         self.compile_expression(*builtin_call.callee)?;
-        let c = self
-            .chunk
-            .add_constant(value::Value::String(builtin_call.name));
-        self.chunk
-            .write_chunk(OpCode::Constant as u8, builtin_call.line);
-        self.chunk.write_chunk(c, builtin_call.line);
+        self.emit_constant(value::Value::String(builtin_call.name), builtin_call.line)?;
         self.adjust_stack_usage(1);
         self.chunk
             .write_chunk(OpCode::BuiltinCall as u8, builtin_call.line);
+        self.chunk.write_chunk(nargs, builtin_call.line);
         self.adjust_stack_usage(-2 - (nargs as i8));
         self.adjust_stack_usage(1);
 
@@ -823,17 +2273,31 @@ impl Compiler {
         Ok(())
     }
 
+    // break and continue leave the environments inside the loop without
+    // popping them, so close any of their locals captured so far. Closures
+    // created later in the loop body can't have run yet.
+    fn close_loop_upvalues(&mut self, line: usize) {
+        let outside = self.loop_contexts.last().unwrap().environments;
+        let captured = self.environments[outside..].iter().find(|e| e.captured);
+        if let Some(env) = captured {
+            let first_local = env.first_local;
+            self.chunk.write_chunk(OpCode::CloseUpvalues as u8, line);
+            self.emit_short(first_local, line);
+        }
+    }
+
     fn compile_continue(&mut self, line: usize) -> Result<()> {
+        self.close_loop_upvalues(line);
         if let Some(loop_context) = self.loop_contexts.last() {
             if loop_context.pushed_this_loop > 0 {
                 self.chunk.write_chunk(OpCode::PopMulti as u8, line);
                 self.chunk.write_chunk(loop_context.pushed_this_loop, line);
             }
-            self.chunk.write_chunk(OpCode::Jump as u8, line);
-            self.chunk.write_chunk(0, line);
-            self.chunk.write_chunk(0, line);
-            let jump_target_address = self.chunk.code.len() - 2;
+            for _ in 0..loop_context.handlers {
+                self.chunk.write_chunk(OpCode::PopHandler as u8, line);
+            }
             let continue_address = loop_context.continue_address;
+            let jump_target_address = self.emit_jump(OpCode::Jump, line);
             self.insert_jump_address(jump_target_address, continue_address);
             self.adjust_stack_usage(1); // Logically this should be an expression returning a value, but it doesn't return.
 
@@ -844,6 +2308,7 @@ impl Compiler {
     }
 
     fn compile_break(&mut self, line: usize) -> Result<()> {
+        self.close_loop_upvalues(line);
         if let Some(loop_context) = self.loop_contexts.last_mut() {
             if loop_context.pushed_this_loop > 0 {
                 self.chunk.write_chunk(OpCode::PopMulti as u8, line);
@@ -852,10 +2317,11 @@ impl Compiler {
             if loop_context.break_pop {
                 self.chunk.write_chunk(OpCode::Pop as u8, line);
             }
-            self.chunk.write_chunk(OpCode::Jump as u8, line);
-            self.chunk.write_chunk(0, line);
-            self.chunk.write_chunk(0, line);
-            loop_context.breaks.push(self.chunk.code.len() - 2);
+            for _ in 0..loop_context.handlers {
+                self.chunk.write_chunk(OpCode::PopHandler as u8, line);
+            }
+            let jump = self.emit_jump(OpCode::Jump, line);
+            self.loop_contexts.last_mut().unwrap().breaks.push(jump);
             self.adjust_stack_usage(1); // Logically this should be an expression returning a value, but it doesn't return.
 
             return Ok(());
@@ -0,0 +1,238 @@
+use super::gc::Heap;
+use super::value::{HashableValue, ReferenceType, Value};
+use super::vm::InterpreterError;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// Conversions between Rust types and Nail values, for use by host programs
+// and native functions. Arrays and maps are allocated on, or read from, the
+// heap. Nothing allocated here is rooted, so the resulting value must be
+// returned to the VM (or held in a Handle) before the next collection.
+
+pub trait IntoNail {
+    fn into_nail(self, heap: &mut Heap) -> Result<Value, InterpreterError>;
+}
+
+pub trait FromNail: Sized {
+    fn from_nail(value: &Value, heap: &Heap) -> Result<Self, InterpreterError>;
+}
+
+// Conversion errors have no line; natives report them at the line of the
+// call.
+fn expected<T>(expected: &str, value: &Value, heap: &Heap) -> Result<T, InterpreterError> {
+    Err(InterpreterError::RuntimeError(
+        format!("Expected {} but got {}", expected, type_name(value, heap)),
+        0,
+        Vec::new(),
+    ))
+}
+
+pub(crate) fn type_name(value: &Value, heap: &Heap) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Number(_) => "number",
+        Value::Boolean(_) => "bool",
+        Value::String(_) => "string",
+        Value::Range(..) => "range",
+        Value::MapForContext(..) => "map iterator",
+        Value::Callable(_) | Value::NativeFunction(_) => "function",
+        Value::ReferenceId(id) => match &heap[*id] {
+            ReferenceType::Array(_) => "array",
+            ReferenceType::Map(_) => "map",
+            ReferenceType::External(e) => e.type_name(),
+            ReferenceType::Closure(_) => "function",
+            ReferenceType::Upvalue(_) => "upvalue",
+            ReferenceType::Instance(_) => "struct instance",
+            ReferenceType::Enum(_) => "enum value",
+        },
+    }
+}
+
+impl IntoNail for Value {
+    fn into_nail(self, _heap: &mut Heap) -> Result<Value, InterpreterError> {
+        Ok(self)
+    }
+}
+
+impl FromNail for Value {
+    fn from_nail(value: &Value, _heap: &Heap) -> Result<Self, InterpreterError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoNail for () {
+    fn into_nail(self, _heap: &mut Heap) -> Result<Value, InterpreterError> {
+        Ok(Value::Nil)
+    }
+}
+
+impl IntoNail for f64 {
+    fn into_nail(self, _heap: &mut Heap) -> Result<Value, InterpreterError> {
+        Ok(Value::Number(self))
+    }
+}
+
+impl FromNail for f64 {
+    fn from_nail(value: &Value, heap: &Heap) -> Result<Self, InterpreterError> {
+        match value {
+            Value::Number(n) => Ok(*n),
+            _ => expected("number", value, heap),
+        }
+    }
+}
+
+impl IntoNail for f32 {
+    fn into_nail(self, _heap: &mut Heap) -> Result<Value, InterpreterError> {
+        Ok(Value::Number(f64::from(self)))
+    }
+}
+
+impl FromNail for f32 {
+    fn from_nail(value: &Value, heap: &Heap) -> Result<Self, InterpreterError> {
+        f64::from_nail(value, heap).map(|n| n as f32)
+    }
+}
+
+// Numbers are f64, so integers convert back only if they're whole and in
+// range for the target type. MAX itself may round up as an f64 (to 2^64 for
+// u64), but MAX + 1 is a power of two and exact, so it's the bound.
+macro_rules! integer_conversions {
+    ( $( $type:ty ),* ) => {
+        $(
+            impl IntoNail for $type {
+                fn into_nail(self, _heap: &mut Heap) -> Result<Value, InterpreterError> {
+                    Ok(Value::Number(self as f64))
+                }
+            }
+
+            impl FromNail for $type {
+                fn from_nail(value: &Value, heap: &Heap) -> Result<Self, InterpreterError> {
+                    match value {
+                        Value::Number(n)
+                            if n.fract() == 0.0
+                                && *n >= <$type>::MIN as f64
+                                && *n < <$type>::MAX as f64 + 1.0 =>
+                        {
+                            Ok(*n as $type)
+                        }
+                        Value::Number(n) => Err(InterpreterError::RuntimeError(
+                            format!("Expected {} but got {}", stringify!($type), n),
+                            0,
+                            Vec::new(),
+                        )),
+                        _ => expected(stringify!($type), value, heap),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoNail for bool {
+    fn into_nail(self, _heap: &mut Heap) -> Result<Value, InterpreterError> {
+        Ok(Value::Boolean(self))
+    }
+}
+
+impl FromNail for bool {
+    fn from_nail(value: &Value, heap: &Heap) -> Result<Self, InterpreterError> {
+        match value {
+            Value::Boolean(b) => Ok(*b),
+            _ => expected("bool", value, heap),
+        }
+    }
+}
+
+impl IntoNail for String {
+    fn into_nail(self, _heap: &mut Heap) -> Result<Value, InterpreterError> {
+        Ok(Value::String(self))
+    }
+}
+
+impl IntoNail for &str {
+    fn into_nail(self, _heap: &mut Heap) -> Result<Value, InterpreterError> {
+        Ok(Value::String(self.to_string()))
+    }
+}
+
+impl FromNail for String {
+    fn from_nail(value: &Value, heap: &Heap) -> Result<Self, InterpreterError> {
+        match value {
+            Value::String(s) => Ok(s.clone()),
+            _ => expected("string", value, heap),
+        }
+    }
+}
+
+// None is nil.
+impl<T: IntoNail> IntoNail for Option<T> {
+    fn into_nail(self, heap: &mut Heap) -> Result<Value, InterpreterError> {
+        match self {
+            Some(v) => v.into_nail(heap),
+            None => Ok(Value::Nil),
+        }
+    }
+}
+
+impl<T: FromNail> FromNail for Option<T> {
+    fn from_nail(value: &Value, heap: &Heap) -> Result<Self, InterpreterError> {
+        match value {
+            Value::Nil => Ok(None),
+            _ => T::from_nail(value, heap).map(Some),
+        }
+    }
+}
+
+impl<T: IntoNail> IntoNail for Vec<T> {
+    fn into_nail(self, heap: &mut Heap) -> Result<Value, InterpreterError> {
+        let array = self
+            .into_iter()
+            .map(|v| v.into_nail(heap))
+            .collect::<Result<_, _>>()?;
+        Ok(Value::ReferenceId(
+            heap.allocate(ReferenceType::Array(array)),
+        ))
+    }
+}
+
+impl<T: FromNail> FromNail for Vec<T> {
+    fn from_nail(value: &Value, heap: &Heap) -> Result<Self, InterpreterError> {
+        match value {
+            Value::ReferenceId(id) => match &heap[*id] {
+                ReferenceType::Array(a) => a.iter().map(|v| T::from_nail(v, heap)).collect(),
+                _ => expected("array", value, heap),
+            },
+            _ => expected("array", value, heap),
+        }
+    }
+}
+
+impl<K: IntoNail, V: IntoNail> IntoNail for HashMap<K, V> {
+    fn into_nail(self, heap: &mut Heap) -> Result<Value, InterpreterError> {
+        let mut map = HashMap::new();
+        for (k, v) in self {
+            let key = HashableValue::try_from(&k.into_nail(heap)?, 0)?;
+            map.insert(key, v.into_nail(heap)?);
+        }
+        Ok(Value::ReferenceId(heap.allocate(ReferenceType::Map(map))))
+    }
+}
+
+impl<K: FromNail + Eq + Hash, V: FromNail> FromNail for HashMap<K, V> {
+    fn from_nail(value: &Value, heap: &Heap) -> Result<Self, InterpreterError> {
+        match value {
+            Value::ReferenceId(id) => match &heap[*id] {
+                ReferenceType::Map(m) => m
+                    .iter()
+                    .map(|(k, v)| {
+                        Ok((K::from_nail(&Value::from(k), heap)?, V::from_nail(v, heap)?))
+                    })
+                    .collect(),
+                _ => expected("map", value, heap),
+            },
+            _ => expected("map", value, heap),
+        }
+    }
+}
@@ -1,4 +1,24 @@
 use super::chunk::*;
+use super::{errors::Result, scanner};
+
+pub fn print_tokens(source: &str) -> Result<()> {
+    let mut scanner = scanner::Scanner::new(source);
+    let mut line = usize::MAX;
+    loop {
+        let token = scanner.scan_token()?;
+        if token.line != line {
+            print!("{:4} ", token.line);
+            line = token.line;
+        } else {
+            print!("   | ");
+        }
+        println!("{:?} '{}'", token.token_type, scanner.get_lexeme(&token));
+
+        if token.token_type == scanner::TokenType::EOF {
+            return Ok(());
+        }
+    }
+}
 
 pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
     println!("== {} ==", name);
@@ -38,8 +58,8 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
         Some(OpCode::PushNil) => simple_instruction("OP_PUSH_NIL", offset),
         Some(OpCode::Pop) => simple_instruction("OP_POP", offset),
 
-        Some(OpCode::FunctionEntry) => number_instruction("OP_FN_ENTRY", &chunk, offset),
-        Some(OpCode::Call) => simple_instruction("OP_CALL", offset),
+        Some(OpCode::FunctionEntry) => short_instruction("OP_FN_ENTRY", &chunk, offset),
+        Some(OpCode::Call) => number_instruction("OP_CALL", chunk, offset),
 
         Some(OpCode::JumpIfFalse) => {
             signed_number_16_instruction("OP_JUMP_IF_FALSE", &chunk, offset)
@@ -58,10 +78,10 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
 
         Some(OpCode::IndexAssign) => simple_instruction("OP_INDEX_ASSIGN", offset),
 
-        Some(OpCode::BuiltinCall) => simple_instruction("OP_BUILTIN_CALL", offset),
+        Some(OpCode::BuiltinCall) => number_instruction("OP_BUILTIN_CALL", chunk, offset),
 
         Some(OpCode::MakeRange) => simple_instruction("OP_MAKE_RANGE", offset),
-        Some(OpCode::ForLoop) => for_instruction(&chunk, offset),
+        Some(OpCode::ForLoop) => for_instruction(&chunk, offset, false),
 
         Some(OpCode::Remainder) => simple_instruction("OP_REMAINDER", offset),
 
@@ -85,6 +105,53 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
         Some(OpCode::AssignGlobal) => simple_instruction("OP_ASSIGN_GLOBAL", offset),
         Some(OpCode::LoadGlobal) => simple_instruction("OP_LOAD_GLOBAL", offset),
 
+        Some(OpCode::Closure) => closure_instruction(chunk, offset),
+        Some(OpCode::LoadUpvalue) => number_instruction("OP_LOAD_UPVALUE", chunk, offset),
+        Some(OpCode::AssignUpvalue) => number_instruction("OP_ASSIGN_UPVALUE", chunk, offset),
+        Some(OpCode::CloseUpvalues) => short_instruction("OP_CLOSE_UPVALUES", chunk, offset),
+
+        Some(OpCode::NewInstance) => number_instruction("OP_NEW_INSTANCE", chunk, offset),
+        Some(OpCode::InitField) => number_instruction("OP_INIT_FIELD", chunk, offset),
+        Some(OpCode::GetField) => field_instruction("OP_GET_FIELD", chunk, offset),
+        Some(OpCode::SetField) => field_instruction("OP_SET_FIELD", chunk, offset),
+
+        Some(OpCode::NewEnum) => variant_instruction("OP_NEW_ENUM", chunk, offset),
+        Some(OpCode::MatchVariant) => variant_instruction("OP_MATCH_VARIANT", chunk, offset),
+        Some(OpCode::VariantPayload) => number_instruction("OP_VARIANT_PAYLOAD", chunk, offset),
+        Some(OpCode::MatchArray) => number_instruction("OP_MATCH_ARRAY", chunk, offset),
+        Some(OpCode::MatchRange) => simple_instruction("OP_MATCH_RANGE", offset),
+        Some(OpCode::NoMatch) => simple_instruction("OP_NO_MATCH", offset),
+        Some(OpCode::MatchArrayAtLeast) => {
+            number_instruction("OP_MATCH_ARRAY_AT_LEAST", chunk, offset)
+        }
+        Some(OpCode::ArrayRest) => number_instruction("OP_ARRAY_REST", chunk, offset),
+        Some(OpCode::MatchKey) => simple_instruction("OP_MATCH_KEY", offset),
+        Some(OpCode::GetKey) => simple_instruction("OP_GET_KEY", offset),
+
+        Some(OpCode::Interpolate) => number_instruction("OP_INTERPOLATE", chunk, offset),
+
+        Some(OpCode::PushHandler) => handler_instruction(chunk, offset, false),
+        Some(OpCode::PopHandler) => simple_instruction("OP_POP_HANDLER", offset),
+        Some(OpCode::Throw) => simple_instruction("OP_THROW", offset),
+        Some(OpCode::Exit) => simple_instruction("OP_EXIT", offset),
+
+        Some(OpCode::ConstantLong) => constant_long_instruction(chunk, offset),
+
+        Some(OpCode::JumpLong) => jump_long_instruction("OP_JUMP_LONG", chunk, offset),
+        Some(OpCode::JumpIfFalseLong) => {
+            jump_long_instruction("OP_JUMP_IF_FALSE_LONG", chunk, offset)
+        }
+        Some(OpCode::JumpIfTrueLong) => {
+            jump_long_instruction("OP_JUMP_IF_TRUE_LONG", chunk, offset)
+        }
+        Some(OpCode::ForLoopLong) => for_instruction(chunk, offset, true),
+        Some(OpCode::PushHandlerLong) => handler_instruction(chunk, offset, true),
+
+        Some(OpCode::AssignLocalLong) => short_instruction("OP_ASSIGN_LOCAL_LONG", chunk, offset),
+        Some(OpCode::LoadLocalLong) => short_instruction("OP_LOAD_LOCAL_LONG", chunk, offset),
+
+        Some(OpCode::BadDestructure) => number_instruction("OP_BAD_DESTRUCTURE", chunk, offset),
+
         None => {
             println!("Unknown opcode {}", instr);
             offset + 1
@@ -106,12 +173,53 @@ fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
     offset + 2
 }
 
+fn constant_long_instruction(chunk: &Chunk, offset: usize) -> usize {
+    let constant = long_operand(chunk, offset + 1);
+    println!(
+        "OP_CONSTANT_LONG {} '{}'",
+        constant, chunk.constants[constant]
+    );
+    offset + 4
+}
+
+fn long_operand(chunk: &Chunk, offset: usize) -> usize {
+    chunk.code[offset] as usize
+        | (chunk.code[offset + 1] as usize) << 8
+        | (chunk.code[offset + 2] as usize) << 16
+}
+
 fn number_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
     let number = chunk.code[offset + 1];
     println!("{} {}", name, number);
     offset + 2
 }
 
+fn short_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+    println!("{} {}", name, short_operand(chunk, offset + 1));
+    offset + 3
+}
+
+fn short_operand(chunk: &Chunk, offset: usize) -> usize {
+    chunk.code[offset] as usize | (chunk.code[offset + 1] as usize) << 8
+}
+
+fn field_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+    let field = chunk.code[offset + 1];
+    println!("{} {} '{}'", name, field, chunk.field_names[field as usize]);
+    offset + 2
+}
+
+fn variant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+    let enum_id = chunk.code[offset + 1] as usize;
+    let variant = chunk.code[offset + 2] as usize;
+    let definition = &chunk.enums[enum_id];
+    println!(
+        "{} {} {} '{}::{}'",
+        name, enum_id, variant, definition.name, definition.variants[variant].name
+    );
+    offset + 3
+}
+
 fn signed_number_16_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
     let number = chunk.code[offset + 1];
     let number2 = chunk.code[offset + 2];
@@ -123,14 +231,49 @@ fn signed_number_16_instruction(name: &str, chunk: &Chunk, offset: usize) -> usi
     offset + 3
 }
 
-fn for_instruction(chunk: &Chunk, offset: usize) -> usize {
-    let local = chunk.code[offset + 1];
-    let jump_target = chunk.code[offset + 2];
-    let jump_target2 = chunk.code[offset + 3];
+fn jump_long_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+    println!("{} {}", name, jump_offset(chunk, offset + 1, true));
+    offset + 4
+}
+
+fn handler_instruction(chunk: &Chunk, offset: usize, wide: bool) -> usize {
+    let first_local = short_operand(chunk, offset + 1);
     println!(
-        "OP_FOR_LOOP l={} jt={}",
+        "OP_PUSH_HANDLER{} {} {}",
+        if wide { "_LONG" } else { "" },
+        first_local,
+        jump_offset(chunk, offset + 3, wide)
+    );
+    offset + if wide { 6 } else { 5 }
+}
+
+fn for_instruction(chunk: &Chunk, offset: usize, wide: bool) -> usize {
+    let local = short_operand(chunk, offset + 1);
+    println!(
+        "OP_FOR_LOOP{} l={} jt={}",
+        if wide { "_LONG" } else { "" },
         local,
-        (jump_target as usize | (jump_target2 as usize) << 8) as i16
+        jump_offset(chunk, offset + 3, wide)
     );
-    offset + 4
+    offset + if wide { 6 } else { 5 }
+}
+
+fn jump_offset(chunk: &Chunk, offset: usize, wide: bool) -> isize {
+    if wide {
+        ((long_operand(chunk, offset) as u32) << 8) as i32 as isize >> 8
+    } else {
+        (chunk.code[offset] as usize | (chunk.code[offset + 1] as usize) << 8) as i16 as isize
+    }
+}
+
+fn closure_instruction(chunk: &Chunk, offset: usize) -> usize {
+    let count = chunk.code[offset + 1] as usize;
+    print!("OP_CLOSURE upvalues=");
+    for i in 0..count {
+        let is_local = chunk.code[offset + 2 + i * 3];
+        let index = short_operand(chunk, offset + 3 + i * 3);
+        print!("{}{} ", if is_local == 1 { "l" } else { "u" }, index);
+    }
+    println!();
+    offset + 2 + count * 3
 }
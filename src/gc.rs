@@ -0,0 +1,193 @@
+use super::value::{HashableValue, ReferenceType, Upvalue, Value};
+
+// Number of live objects before the first collection is triggered.
+const DEFAULT_GC_THRESHOLD: usize = 1024 * 1024;
+// After a collection the next one is scheduled once the live object count
+// has grown by this factor (but never below the configured threshold).
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
+// The heap is a simple mark and sweep collected arena. ReferenceIds are
+// indices into objects. Freed slots are set to None and their index pushed
+// on the free list, so ids get reused by later allocations. Outside the
+// crate (natives and conversions) objects can only be allocated and
+// accessed; collecting is left to the VM, which knows the roots.
+pub struct Heap {
+    objects: Vec<Option<ReferenceType>>,
+    marks: Vec<bool>,
+    free_list: Vec<usize>,
+    gray: Vec<usize>,
+    live: usize,
+    threshold: usize,
+    next_gc: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free_list: Vec::new(),
+            gray: Vec::new(),
+            live: 0,
+            threshold: DEFAULT_GC_THRESHOLD,
+            next_gc: DEFAULT_GC_THRESHOLD,
+        }
+    }
+
+    pub fn allocate(&mut self, value: ReferenceType) -> usize {
+        self.live += 1;
+        if let Some(id) = self.free_list.pop() {
+            self.objects[id] = Some(value);
+            id
+        } else {
+            self.objects.push(Some(value));
+            self.marks.push(false);
+            self.objects.len() - 1
+        }
+    }
+
+    pub fn get(&self, id: usize) -> Option<&ReferenceType> {
+        self.objects.get(id).and_then(Option::as_ref)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut ReferenceType> {
+        self.objects.get_mut(id).and_then(Option::as_mut)
+    }
+
+    pub(crate) fn live_objects(&self) -> usize {
+        self.live
+    }
+
+    pub(crate) fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold;
+        self.next_gc = threshold;
+    }
+
+    pub(crate) fn should_collect(&self) -> bool {
+        self.live >= self.next_gc
+    }
+
+    pub(crate) fn mark_value(&mut self, value: &Value) {
+        mark_value(&mut self.marks, &mut self.gray, value);
+    }
+
+    pub(crate) fn mark_object(&mut self, id: usize) {
+        mark_object(&mut self.marks, &mut self.gray, id);
+    }
+
+    // Process the gray stack until everything reachable from the marked
+    // roots has been marked.
+    pub(crate) fn trace(&mut self) {
+        let Heap {
+            objects,
+            marks,
+            gray,
+            ..
+        } = self;
+        while let Some(id) = gray.pop() {
+            match &objects[id] {
+                Some(ReferenceType::Array(a)) => {
+                    for v in a {
+                        mark_value(marks, gray, v);
+                    }
+                }
+                Some(ReferenceType::Map(m)) => {
+                    for (k, v) in m {
+                        mark_hashable_value(marks, gray, k);
+                        mark_value(marks, gray, v);
+                    }
+                }
+                Some(ReferenceType::Closure(c)) => {
+                    for u in &c.upvalues {
+                        mark_object(marks, gray, *u);
+                    }
+                }
+                Some(ReferenceType::Instance(i)) => {
+                    for v in &i.fields {
+                        mark_value(marks, gray, v);
+                    }
+                }
+                Some(ReferenceType::Enum(e)) => {
+                    for v in &e.payload {
+                        mark_value(marks, gray, v);
+                    }
+                }
+                Some(ReferenceType::Upvalue(Upvalue::Closed(v))) => mark_value(marks, gray, v),
+                Some(ReferenceType::Upvalue(Upvalue::Open(_))) => {}
+                Some(ReferenceType::External(e)) => {
+                    for v in &e.trace() {
+                        mark_value(marks, gray, v);
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+
+    // Free every unmarked object and clear marks ready for the next cycle.
+    // Returns the number of objects freed.
+    pub(crate) fn sweep(&mut self) -> usize {
+        let mut freed = 0;
+        for id in 0..self.objects.len() {
+            if self.marks[id] {
+                self.marks[id] = false;
+            } else if self.objects[id].is_some() {
+                self.objects[id] = None;
+                self.free_list.push(id);
+                freed += 1;
+            }
+        }
+        self.live -= freed;
+        self.next_gc = std::cmp::max(self.threshold, self.live * GC_HEAP_GROW_FACTOR);
+        freed
+    }
+}
+
+impl std::ops::Index<usize> for Heap {
+    type Output = ReferenceType;
+
+    fn index(&self, id: usize) -> &ReferenceType {
+        self.objects[id]
+            .as_ref()
+            .expect("Attempted to access freed heap object.")
+    }
+}
+
+impl std::ops::IndexMut<usize> for Heap {
+    fn index_mut(&mut self, id: usize) -> &mut ReferenceType {
+        self.objects[id]
+            .as_mut()
+            .expect("Attempted to access freed heap object.")
+    }
+}
+
+fn mark_object(marks: &mut [bool], gray: &mut Vec<usize>, id: usize) {
+    if !marks[id] {
+        marks[id] = true;
+        gray.push(id);
+    }
+}
+
+fn mark_value(marks: &mut [bool], gray: &mut Vec<usize>, value: &Value) {
+    match value {
+        Value::ReferenceId(id) => mark_object(marks, gray, *id),
+        Value::MapForContext(keys, _, _) => {
+            for k in keys {
+                mark_hashable_value(marks, gray, k);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn mark_hashable_value(marks: &mut [bool], gray: &mut Vec<usize>, value: &HashableValue) {
+    if let HashableValue::ReferenceId(id) = value {
+        mark_object(marks, gray, *id);
+    }
+}
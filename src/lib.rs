@@ -1,63 +1,217 @@
 pub mod chunk;
 mod compiler;
+mod convert;
 pub mod debug;
 mod errors;
+mod gc;
 mod parser;
 pub mod scanner;
 mod value;
 pub mod vm;
 
+pub use compiler::CompileOptions;
+pub use convert::{FromNail, IntoNail};
+pub use gc::Heap;
+pub use value::{no_such_method, ExternalType, HashableValue, ReferenceType, Value, ValueOrRef};
+
 use std::io::Write;
 use std::time::Instant;
 
-pub fn repl() {
-    let mut vm = vm::VM::new();
-    loop {
-        print!("> ");
-        std::io::stdout().flush().unwrap();
-
-        let mut line = String::new();
+pub fn repl(options: vm::VmOptions) {
+    let mut vm = vm::VM::with_options(options);
+    let mut history = Vec::new();
+    while let Some(entry) = read_entry() {
+        let entry = entry.trim_end();
+        if entry.trim().is_empty() {
+            continue;
+        }
+        history.push(entry.to_string());
 
-        let result = std::io::stdin().read_line(&mut line);
+        let result = if entry.starts_with(':') {
+            let mut words = entry.split_whitespace();
+            match (words.next().unwrap(), words.next()) {
+                (":reset", None) => {
+                    vm = vm::VM::with_options(options);
+                    continue;
+                }
+                (":load", Some(filename)) => match std::fs::read_to_string(filename) {
+                    Ok(code) => vm.interpret_repl_file(&code, std::path::Path::new(filename)),
+                    Err(e) => {
+                        println!("Unable to read file {}: {}", filename, e);
+                        continue;
+                    }
+                },
+                (":disasm", None) => {
+                    debug::disassemble_chunk(vm.chunk(), "repl");
+                    continue;
+                }
+                (":history", None) => {
+                    for (i, h) in history.iter().enumerate() {
+                        println!("{:4} {}", i + 1, h.replace('\n', "\n     "));
+                    }
+                    continue;
+                }
+                _ => {
+                    println!("Commands: :reset, :load <file>, :disasm, :history");
+                    continue;
+                }
+            }
+        } else {
+            vm.interpret_repl(entry)
+        };
         match result {
-            Ok(_) => {}
+            Ok(value::Value::Nil) => {}
+            Ok(value) => println!("{}", vm.display_value(&value)),
+            Err(vm::InterpreterError::Exit(status)) => std::process::exit(status),
             Err(e) => {
                 println!("{}", e);
-                break;
             }
         }
+    }
+}
 
-        let result = vm.interpret(&format!("fn main() {{{}}}", line));
-        match result {
+// Read lines until they make up a complete entry, or a blank line ends it
+// early. Returns None at the end of input.
+fn read_entry() -> Option<String> {
+    let mut entry = String::new();
+    loop {
+        print!("{}", if entry.is_empty() { "> " } else { ". " });
+        std::io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) if entry.is_empty() => return None,
+            Ok(0) => return Some(entry),
             Ok(_) => {}
             Err(e) => {
                 println!("{}", e);
+                return None;
             }
         }
+        entry.push_str(&line);
+
+        if line.trim().is_empty() || entry.starts_with(':') || parser::is_complete(&entry) {
+            return Some(entry);
+        }
     }
 }
 
-pub fn run_file(filename: &str) {
+// Process exit codes, following the BSD sysexits.h convention like clox.
+pub const EXIT_USAGE: i32 = 64;
+pub const EXIT_COMPILE_ERROR: i32 = 65;
+pub const EXIT_RUNTIME_ERROR: i32 = 70;
+pub const EXIT_IO_ERROR: i32 = 74;
+
+fn read_file(filename: &str) -> Result<String, i32> {
+    std::fs::read_to_string(filename).map_err(|e| {
+        eprintln!("Unable to read file {}: {}", filename, e);
+        EXIT_IO_ERROR
+    })
+}
+
+// Each of the functions below runs a nailc subcommand on a file, returning
+// the exit code.
+
+// main's return value is used as the exit code if it's a number.
+pub fn run_file(filename: &str, args: Vec<String>, options: vm::VmOptions) -> i32 {
     let start = Instant::now();
-    let result = std::fs::read_to_string(filename);
-    let code = result.unwrap_or_else(|_| panic!("Unable to read file {}", filename));
+    let code = match read_file(filename) {
+        Ok(code) => code,
+        Err(status) => return status,
+    };
     let read_file_done = Instant::now();
 
-    let mut vm = vm::VM::new();
-    let result = vm.interpret(&code);
-    match result {
-        Ok(_) => {}
+    let mut vm = vm::VM::with_options(options);
+    vm.set_args(args);
+    let result = vm.interpret_file(&code, std::path::Path::new(filename));
+    let status = match result {
+        Ok(value::Value::Number(n)) => match vm::exit_status(n) {
+            Some(status) => status,
+            None => {
+                eprintln!(
+                    "Runtime Error: {} (returned from main)",
+                    vm::bad_exit_status(n)
+                );
+                return EXIT_RUNTIME_ERROR;
+            }
+        },
+        Ok(_) => 0,
+        Err(vm::InterpreterError::Exit(status)) => status,
+        Err(e @ vm::InterpreterError::CompileError(_)) => {
+            eprintln!("{}", e);
+            return EXIT_COMPILE_ERROR;
+        }
         Err(e) => {
-            println!("{}", e);
-            return;
+            eprintln!("{}", e);
+            return EXIT_RUNTIME_ERROR;
         }
-    }
+    };
     let finished = Instant::now();
-    println!(
-        "Done. File read: {}s {}ms, Interpreted: {}s {}ms.",
-        read_file_done.duration_since(start).as_secs(),
-        read_file_done.duration_since(start).subsec_millis(),
-        finished.duration_since(read_file_done).as_secs(),
-        finished.duration_since(read_file_done).subsec_millis()
-    );
+    if options.timing {
+        eprintln!(
+            "Done. File read: {}s {}ms, Interpreted: {}s {}ms.",
+            read_file_done.duration_since(start).as_secs(),
+            read_file_done.duration_since(start).subsec_millis(),
+            finished.duration_since(read_file_done).as_secs(),
+            finished.duration_since(read_file_done).subsec_millis()
+        );
+    }
+    status
+}
+
+fn compile_file(filename: &str) -> Result<chunk::Chunk, i32> {
+    let code = read_file(filename)?;
+    let options = CompileOptions::default();
+    compiler::compile(&code, Some(std::path::Path::new(filename)), &options, &[]).map_err(|e| {
+        eprintln!("{}: {}", filename, e);
+        EXIT_COMPILE_ERROR
+    })
+}
+
+pub fn check_file(filename: &str) -> i32 {
+    match compile_file(filename) {
+        Ok(_) => 0,
+        Err(status) => status,
+    }
+}
+
+pub fn disassemble_file(filename: &str) -> i32 {
+    match compile_file(filename) {
+        Ok(chunk) => {
+            debug::disassemble_chunk(&chunk, filename);
+            0
+        }
+        Err(status) => status,
+    }
+}
+
+pub fn print_file_tokens(filename: &str) -> i32 {
+    let code = match read_file(filename) {
+        Ok(code) => code,
+        Err(status) => return status,
+    };
+    match debug::print_tokens(&code) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}: {}", filename, e);
+            EXIT_COMPILE_ERROR
+        }
+    }
+}
+
+pub fn print_file_ast(filename: &str) -> i32 {
+    let code = match read_file(filename) {
+        Ok(code) => code,
+        Err(status) => return status,
+    };
+    match parser::parse(&code) {
+        Ok(ast) => {
+            println!("{:#?}", ast);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}: {}", filename, e);
+            EXIT_COMPILE_ERROR
+        }
+    }
 }
@@ -1,12 +1,72 @@
 use nail;
 
+const USAGE: &str = "Usage: nailc [options] [run] <file> [args...]
+       nailc [options]
+       nailc check|disasm|tokens|ast <file>
+
+With no file, starts a REPL.
+
+Commands:
+    run      Run a script (the default)
+    check    Parse and compile a script, reporting any errors
+    disasm   Print a script's bytecode
+    tokens   Print the tokens the scanner produces for a script
+    ast      Print a script's syntax tree
+
+Options:
+    --dump-tokens      Print tokens before compiling
+    --dump-ast         Print the syntax tree before compiling
+    --dump-bytecode    Print bytecode after compiling
+    --timing           Print compile and run times";
+
+fn usage() -> i32 {
+    eprintln!("{}", USAGE);
+    nail::EXIT_USAGE
+}
+
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() == 1 {
-        nail::repl();
-    } else if args.len() == 2 {
-        nail::run_file(&args[1]);
-    } else {
-        println!("Usage: clox [path]");
+    let mut options = nail::vm::VmOptions::default();
+    let mut command = None;
+    let mut file = None;
+    let mut args = Vec::new();
+    for arg in std::env::args().skip(1) {
+        // Everything after the file belongs to the script.
+        if file.is_some() {
+            args.push(arg);
+            continue;
+        }
+        match arg.as_str() {
+            "--dump-tokens" => options.compile.dump_tokens = true,
+            "--dump-ast" => options.compile.dump_ast = true,
+            "--dump-bytecode" => options.compile.dump_bytecode = true,
+            "--timing" => options.timing = true,
+            "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                std::process::exit(usage());
+            }
+            "run" | "check" | "disasm" | "tokens" | "ast" if command.is_none() => {
+                command = Some(arg)
+            }
+            _ => file = Some(arg),
+        }
     }
+
+    let status = match (command.as_deref(), file) {
+        (None, None) => {
+            nail::repl(options);
+            0
+        }
+        (None, Some(file)) | (Some("run"), Some(file)) => nail::run_file(&file, args, options),
+        (Some(_), Some(_)) if !args.is_empty() => usage(),
+        (Some("check"), Some(file)) => nail::check_file(&file),
+        (Some("disasm"), Some(file)) => nail::disassemble_file(&file),
+        (Some("tokens"), Some(file)) => nail::print_file_tokens(&file),
+        (Some("ast"), Some(file)) => nail::print_file_ast(&file),
+        _ => usage(),
+    };
+    std::process::exit(status);
 }
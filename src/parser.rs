@@ -6,6 +6,9 @@ struct Parser {
     scanner: scanner::Scanner,
     previous: Option<scanner::Token>,
     next: scanner::Token,
+    // As in Rust, struct literals aren't allowed directly in if/while/for
+    // heads, where `name {` starts the block instead.
+    struct_literals_allowed: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -85,7 +88,7 @@ pub struct While {
 #[derive(Debug, Clone)]
 pub struct For {
     pub variable: String,
-    pub variable2: Option<String>,
+    pub value: Option<Pattern>,
     pub range: Box<Expression>,
     pub block: Block,
     pub line: usize,
@@ -101,6 +104,7 @@ pub struct Loop {
 pub enum LValue {
     Variable(Variable),
     Index(Index),
+    Field(Field),
 }
 
 #[derive(Debug, Clone)]
@@ -163,6 +167,105 @@ pub struct Return {
     pub line: usize,
 }
 
+#[derive(Debug, Clone)]
+pub struct Throw {
+    pub value: Box<Expression>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Try {
+    pub block: Block,
+    pub error_name: String,
+    pub catch_block: Block,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub object: Box<Expression>,
+    pub name: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldInitializer {
+    pub name: String,
+    pub value: Box<Expression>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct StructLiteral {
+    pub name: String,
+    pub initializers: Vec<FieldInitializer>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Lambda {
+    pub args: Vec<String>,
+    pub block: Block,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Interpolation {
+    pub parts: Vec<Expression>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Path {
+    pub base: String,
+    pub name: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct VariantPattern {
+    pub path: Path,
+    pub patterns: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArrayPattern {
+    pub elements: Vec<Pattern>,
+    pub rest: Option<Box<Pattern>>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyPattern {
+    pub key: String,
+    pub pattern: Pattern,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Binding(String, usize),
+    Literal(Literal),
+    Range(f64, f64, usize),
+    Array(ArrayPattern),
+    Map(Vec<KeyPattern>, usize),
+    Variant(VariantPattern),
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Box<Expression>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub value: Box<Expression>,
+    pub arms: Vec<MatchArm>,
+    pub line: usize,
+}
+
 #[derive(Debug, Clone)]
 pub enum Expression {
     Literal(Literal),
@@ -186,6 +289,14 @@ pub enum Expression {
     Return(Return),
     Break(usize),
     Continue(usize),
+    Lambda(Lambda),
+    Field(Field),
+    StructLiteral(StructLiteral),
+    Path(Path),
+    Match(Match),
+    Interpolation(Interpolation),
+    Throw(Throw),
+    Try(Try),
 }
 
 #[derive(Debug, Clone)]
@@ -207,6 +318,22 @@ pub struct LetStatement {
     pub line: usize,
 }
 
+#[derive(Debug, Clone)]
+pub struct DestructuringLetStatement {
+    pub pattern: Pattern,
+    pub initializer: Expression,
+    pub line: usize,
+}
+
+// name is what the module is referred to as in the importing file, path is
+// the file to load (possibly without the .nlx extension).
+#[derive(Debug, Clone)]
+pub struct ImportStatement {
+    pub path: String,
+    pub name: String,
+    pub line: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct ConstStatement {
     pub name: String,
@@ -222,13 +349,45 @@ pub struct FnStatement {
     pub line: usize,
 }
 
+#[derive(Debug, Clone)]
+pub struct StructStatement {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct VariantDeclaration {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumStatement {
+    pub name: String,
+    pub variants: Vec<VariantDeclaration>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImplStatement {
+    pub name: String,
+    pub methods: Vec<FnStatement>,
+    pub line: usize,
+}
+
 #[derive(Debug, Clone)]
 pub enum Statement {
     ExpressionStatement(ExpressionStatement),
     LetStatement(LetStatement),
+    DestructuringLetStatement(DestructuringLetStatement),
     ConstStatement(ConstStatement),
     PrintStatement(PrintStatement),
     FnStatement(FnStatement),
+    StructStatement(StructStatement),
+    EnumStatement(EnumStatement),
+    ImplStatement(ImplStatement),
+    ImportStatement(ImportStatement),
 }
 
 #[derive(Debug, Clone)]
@@ -244,6 +403,7 @@ impl Parser {
             scanner,
             previous: None,
             next: first,
+            struct_literals_allowed: true,
         })
     }
 
@@ -260,12 +420,40 @@ impl Parser {
         if self.matches(&[TokenType::Fn])? {
             return self.fn_statement();
         }
+        if self.matches(&[TokenType::Struct])? {
+            return self.struct_statement();
+        }
+        if self.matches(&[TokenType::Enum])? {
+            return self.enum_statement();
+        }
+        if self.matches(&[TokenType::Impl])? {
+            return self.impl_statement();
+        }
+        if self.matches(&[TokenType::Import])? {
+            return self.import_statement();
+        }
 
         self.expression_statement()
     }
 
     fn let_statement(&mut self) -> Result<Statement> {
         let line = self.previous().line;
+        if self.check(TokenType::LeftBracket) || self.check(TokenType::HashLeftBrace) {
+            let pattern = self.pattern()?;
+            self.consume(TokenType::Equal, "Expected = after let pattern.")?;
+            let initializer = self.expression()?;
+            self.consume(
+                TokenType::Semicolon,
+                "Expect ';' after variable declaration.",
+            )?;
+            return Ok(Statement::DestructuringLetStatement(
+                DestructuringLetStatement {
+                    pattern,
+                    initializer,
+                    line,
+                },
+            ));
+        }
         let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
 
         let initializer = if self.matches(&[TokenType::Equal])? {
@@ -302,6 +490,26 @@ impl Parser {
         }))
     }
 
+    fn import_statement(&mut self) -> Result<Statement> {
+        let line = self.previous().line;
+        let path = if self.matches(&[TokenType::String])? {
+            self.string_literal(&self.previous())
+        } else {
+            let name = self.consume(TokenType::Identifier, "Expect module name after import.")?;
+            self.scanner.get_lexeme(&name)
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+        let name = std::path::Path::new(&path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Statement::ImportStatement(ImportStatement {
+            path,
+            name,
+            line,
+        }))
+    }
+
     fn print_statement(&mut self) -> Result<Statement> {
         let line = self.previous().line;
         let value = self.expression()?;
@@ -314,6 +522,95 @@ impl Parser {
         let name = self.consume(TokenType::Identifier, "Expected function name.")?;
         let name = self.scanner.get_lexeme(&name);
 
+        let args = self.arg_list()?;
+        let block = self.block()?;
+
+        Ok(Statement::FnStatement(FnStatement {
+            name,
+            args,
+            block,
+            line,
+        }))
+    }
+
+    fn struct_statement(&mut self) -> Result<Statement> {
+        let line = self.previous().line;
+        let name = self.consume(TokenType::Identifier, "Expected struct name.")?;
+        let name = self.scanner.get_lexeme(&name);
+
+        self.consume(TokenType::LeftBrace, "Expected '{' to start struct fields.")?;
+        let mut fields = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            let field = self.consume(TokenType::Identifier, "Expected field name.")?;
+            fields.push(self.scanner.get_lexeme(&field));
+            if !self.matches(&[TokenType::Comma])? {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' to end struct fields.")?;
+
+        Ok(Statement::StructStatement(StructStatement {
+            name,
+            fields,
+            line,
+        }))
+    }
+
+    fn enum_statement(&mut self) -> Result<Statement> {
+        let line = self.previous().line;
+        let name = self.consume(TokenType::Identifier, "Expected enum name.")?;
+        let name = self.scanner.get_lexeme(&name);
+
+        self.consume(TokenType::LeftBrace, "Expected '{' to start enum variants.")?;
+        let mut variants = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            let variant = self.consume(TokenType::Identifier, "Expected variant name.")?;
+            let variant = self.scanner.get_lexeme(&variant);
+            let fields = if self.check(TokenType::LeftParen) {
+                self.arg_list()?
+            } else {
+                Vec::new()
+            };
+            variants.push(VariantDeclaration {
+                name: variant,
+                fields,
+            });
+            if !self.matches(&[TokenType::Comma])? {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' to end enum variants.")?;
+
+        Ok(Statement::EnumStatement(EnumStatement {
+            name,
+            variants,
+            line,
+        }))
+    }
+
+    fn impl_statement(&mut self) -> Result<Statement> {
+        let line = self.previous().line;
+        let name = self.consume(TokenType::Identifier, "Expected struct name after impl.")?;
+        let name = self.scanner.get_lexeme(&name);
+
+        self.consume(TokenType::LeftBrace, "Expected '{' to start impl block.")?;
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            self.consume(TokenType::Fn, "Expected fn in impl block.")?;
+            if let Statement::FnStatement(f) = self.fn_statement()? {
+                methods.push(f);
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' to end impl block.")?;
+
+        Ok(Statement::ImplStatement(ImplStatement {
+            name,
+            methods,
+            line,
+        }))
+    }
+
+    fn arg_list(&mut self) -> Result<Vec<String>> {
         self.consume(TokenType::LeftParen, "Expected '(' for fn arg list")?;
         let mut args = Vec::new();
         if self.matches(&[TokenType::Identifier])? {
@@ -328,15 +625,14 @@ impl Parser {
             }
         }
         self.consume(TokenType::RightParen, "Expected ')' for fn arg list")?;
+        Ok(args)
+    }
 
-        let block = self.block()?;
-
-        Ok(Statement::FnStatement(FnStatement {
-            name,
-            args,
-            block,
-            line,
-        }))
+    // A fn keyword followed by a name is a fn statement, otherwise it starts
+    // an anonymous fn expression.
+    fn is_fn_statement(&mut self) -> Result<bool> {
+        Ok(self.check(TokenType::Fn)
+            && self.scanner.peek_token()?.token_type == TokenType::Identifier)
     }
 
     fn expression_statement(&mut self) -> Result<Statement> {
@@ -352,12 +648,32 @@ impl Parser {
         self.compound_assignment()
     }
 
+    // An expression inside brackets of some kind, where struct literals are
+    // always allowed again.
+    fn delimited_expression(&mut self) -> Result<Expression> {
+        let allowed = self.struct_literals_allowed;
+        self.struct_literals_allowed = true;
+        let expression = self.expression();
+        self.struct_literals_allowed = allowed;
+        expression
+    }
+
+    fn head_expression(&mut self) -> Result<Expression> {
+        let allowed = self.struct_literals_allowed;
+        self.struct_literals_allowed = false;
+        let expression = self.expression();
+        self.struct_literals_allowed = allowed;
+        expression
+    }
+
     fn can_be_statement_without_semicolon(&self, expression: &Expression) -> bool {
         match expression {
             Expression::Block(_) => true,
             Expression::For(_) => true,
             Expression::While(_) => true,
             Expression::If(_) => true,
+            Expression::Match(_) => true,
+            Expression::Try(_) => true,
             _ => false,
         }
     }
@@ -370,10 +686,13 @@ impl Parser {
         // Correct solution may be to insert semicolons in lexer?
         self.consume(TokenType::LeftBrace, "Expected '{' to start block.")?;
         let line = self.previous().line;
+        let allowed = self.struct_literals_allowed;
+        self.struct_literals_allowed = true;
         let mut statements = Vec::new();
         let mut expression = None;
         while self.peek().token_type != TokenType::RightBrace {
-            match self.peek().token_type {
+            let next_type = self.peek().token_type;
+            match next_type {
                 TokenType::Let => {
                     self.consume(TokenType::Let, "This should never happen.")?;
                     statements.push(self.let_statement()?);
@@ -386,10 +705,16 @@ impl Parser {
                     self.consume(TokenType::Print, "This should never happen.")?;
                     statements.push(self.print_statement()?);
                 }
-                TokenType::Fn => {
+                TokenType::Fn if self.is_fn_statement()? => {
                     self.consume(TokenType::Fn, "This should never happen.")?;
                     statements.push(self.fn_statement()?);
                 }
+                TokenType::Import => {
+                    return Err(ParserError(
+                        "Imports are only allowed at top level.".to_string(),
+                        self.peek().line,
+                    ));
+                }
                 _ => {
                     let found_expression = self.expression()?;
                     if self.matches(&[TokenType::Semicolon])?
@@ -408,6 +733,7 @@ impl Parser {
             }
         }
         self.consume(TokenType::RightBrace, "Expected '}' to end block.")?;
+        self.struct_literals_allowed = allowed;
         Ok(Block {
             statements,
             expression,
@@ -415,6 +741,50 @@ impl Parser {
         })
     }
 
+    // A REPL entry is a sequence of statements like a file, except it can
+    // also contain expression statements and end in a bare expression.
+    fn repl_entry(&mut self) -> Result<Block> {
+        let mut statements = Vec::new();
+        let mut expression = None;
+        while !self.is_at_end() {
+            let next_type = self.peek().token_type;
+            match next_type {
+                TokenType::Let
+                | TokenType::Const
+                | TokenType::Print
+                | TokenType::Struct
+                | TokenType::Enum
+                | TokenType::Impl
+                | TokenType::Import => statements.push(self.statement()?),
+                TokenType::Fn if self.is_fn_statement()? => statements.push(self.statement()?),
+                _ => {
+                    let found_expression = self.expression()?;
+                    if self.matches(&[TokenType::Semicolon])?
+                        || (self.can_be_statement_without_semicolon(&found_expression)
+                            && !self.is_at_end())
+                    {
+                        statements.push(Statement::ExpressionStatement(ExpressionStatement {
+                            expression: found_expression,
+                            line: self.previous().line,
+                        }))
+                    } else if self.is_at_end() {
+                        expression = Some(Box::new(found_expression));
+                    } else {
+                        return Err(ParserError(
+                            "Expect ';' after expression.".to_string(),
+                            self.peek().line,
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(Block {
+            statements,
+            expression,
+            line: 1,
+        })
+    }
+
     fn compound_assignment(&mut self) -> Result<Expression> {
         let mut expr = self.assignment()?;
         while self.matches(&[
@@ -443,6 +813,14 @@ impl Parser {
                         line,
                     })
                 }
+                Expression::Field(f) => {
+                    expr = Expression::CompoundAssignment(CompoundAssignment {
+                        lvalue: LValue::Field(f),
+                        operator,
+                        value: Box::new(value),
+                        line,
+                    })
+                }
                 _ => {
                     return Err(ParserError(
                         "Not a valid LValue in assignment".to_string(),
@@ -474,6 +852,13 @@ impl Parser {
                         line,
                     })
                 }
+                Expression::Field(f) => {
+                    expr = Expression::Assignment(Assignment {
+                        lvalue: LValue::Field(f),
+                        value: Box::new(value),
+                        line,
+                    })
+                }
                 _ => {
                     return Err(ParserError(
                         "Not a valid LValue in assignment".to_string(),
@@ -615,7 +1000,7 @@ impl Parser {
 
     fn finish_index(&mut self, indexer: Expression) -> Result<Expression> {
         let line = self.previous().line;
-        let value = self.expression()?;
+        let value = self.delimited_expression()?;
         self.consume(TokenType::RightBracket, "Expected ']' after arguments.")?;
 
         Ok(Expression::Index(Index {
@@ -633,9 +1018,9 @@ impl Parser {
         )?;
         let name = self.scanner.get_lexeme(&name);
 
-        Ok(Expression::Index(Index {
-            indexer: Box::new(indexer),
-            value: Box::new(Expression::Literal(Literal::String(name, line))),
+        Ok(Expression::Field(Field {
+            object: Box::new(indexer),
+            name,
             line,
         }))
     }
@@ -645,7 +1030,7 @@ impl Parser {
         let mut args = Vec::new();
         if !self.check(TokenType::RightParen) {
             loop {
-                args.push(self.expression()?);
+                args.push(self.delimited_expression()?);
                 if !self.matches(&[TokenType::Comma])? {
                     break;
                 }
@@ -663,13 +1048,18 @@ impl Parser {
 
     fn finish_builtin_call(&mut self, callee: Expression) -> Result<Expression> {
         let line = self.previous().line;
-        let name = self.consume(TokenType::Identifier, "Expected builtin name.")?;
+        // Regexes have a match method, so allow the keyword as a name here.
+        let name = if self.check(TokenType::Match) {
+            self.advance()?
+        } else {
+            self.consume(TokenType::Identifier, "Expected builtin name.")?
+        };
 
         self.consume(TokenType::LeftParen, "Expected '(' to start arguments.")?;
         let mut args = Vec::new();
         if !self.check(TokenType::RightParen) {
             loop {
-                args.push(self.expression()?);
+                args.push(self.delimited_expression()?);
                 if !self.matches(&[TokenType::Comma])? {
                     break;
                 }
@@ -688,7 +1078,7 @@ impl Parser {
 
     fn if_expression(&mut self) -> Result<Expression> {
         let line = self.previous().line;
-        let condition = Box::new(self.expression()?);
+        let condition = Box::new(self.head_expression()?);
         let then_block = self.block()?;
         let mut else_expression = None;
         if self.matches(&[TokenType::Else])? {
@@ -708,7 +1098,7 @@ impl Parser {
 
     fn while_expression(&mut self) -> Result<Expression> {
         let line = self.previous().line;
-        let condition = Box::new(self.expression()?);
+        let condition = Box::new(self.head_expression()?);
         let block = self.block()?;
         Ok(Expression::While(While {
             condition,
@@ -719,23 +1109,26 @@ impl Parser {
 
     fn for_expression(&mut self) -> Result<Expression> {
         let line = self.previous().line;
-        let variable = self.consume(TokenType::Identifier, "Expected identifier in for loop")?;
-        let variable = self.scanner.get_lexeme(&variable);
-        let variable2 = if self.matches(&[TokenType::Comma])? {
-            let variable2t = self.consume(
-                TokenType::Identifier,
-                "Expected second identifier in for loop",
-            )?;
-            Some(self.scanner.get_lexeme(&variable2t))
+        // A lone pattern destructures each value, otherwise the first name
+        // gets the index/key and an optional second pattern the value.
+        let (variable, value) = if self.check(TokenType::Identifier) {
+            let variable = self.advance()?;
+            let variable = self.scanner.get_lexeme(&variable);
+            let value = if self.matches(&[TokenType::Comma])? {
+                Some(self.pattern()?)
+            } else {
+                None
+            };
+            (variable, value)
         } else {
-            None
+            ("_for_loop_index".to_string(), Some(self.pattern()?))
         };
         self.consume(TokenType::In, "Expected 'in' in for loop.")?;
-        let range = self.expression()?;
+        let range = self.head_expression()?;
         let block = self.block()?;
         Ok(Expression::For(For {
             variable,
-            variable2,
+            value,
             range: Box::new(range),
             block,
             line,
@@ -759,6 +1152,224 @@ impl Parser {
         Ok(Expression::Return(Return { value, line }))
     }
 
+    fn match_expression(&mut self) -> Result<Expression> {
+        let line = self.previous().line;
+        let value = self.head_expression()?;
+        self.consume(TokenType::LeftBrace, "Expected '{' to start match arms.")?;
+        let mut arms = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            let pattern = self.pattern()?;
+            let line = self
+                .consume(TokenType::FatArrow, "Expected '=>' after match pattern.")?
+                .line;
+            let body = self.delimited_expression()?;
+            let is_block = matches!(body, Expression::Block(_));
+            arms.push(MatchArm {
+                pattern,
+                body: Box::new(body),
+                line,
+            });
+            if !self.matches(&[TokenType::Comma])? && !is_block {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' to end match arms.")?;
+        Ok(Expression::Match(Match {
+            value: Box::new(value),
+            arms,
+            line,
+        }))
+    }
+
+    fn pattern(&mut self) -> Result<Pattern> {
+        let line = self.peek().line;
+        if self.matches(&[TokenType::LeftBracket])? {
+            let mut elements = Vec::new();
+            let mut rest = None;
+            while !self.check(TokenType::RightBracket) {
+                if self.matches(&[TokenType::DotDot])? {
+                    // The rest of the array, which must come last.
+                    let pattern = if self.matches(&[TokenType::Identifier])? {
+                        let t = self.previous();
+                        Pattern::Binding(self.scanner.get_lexeme(&t), t.line)
+                    } else {
+                        Pattern::Wildcard
+                    };
+                    rest = Some(Box::new(pattern));
+                    self.matches(&[TokenType::Comma])?;
+                    break;
+                }
+                elements.push(self.pattern()?);
+                if !self.matches(&[TokenType::Comma])? {
+                    break;
+                }
+            }
+            self.consume(
+                TokenType::RightBracket,
+                "Expected ']' to end array pattern.",
+            )?;
+            return Ok(Pattern::Array(ArrayPattern {
+                elements,
+                rest,
+                line,
+            }));
+        }
+        if self.matches(&[TokenType::HashLeftBrace])? {
+            let mut keys = Vec::new();
+            while !self.check(TokenType::RightBrace) {
+                let t = self.consume(TokenType::Identifier, "Expected key in map pattern.")?;
+                let key = self.scanner.get_lexeme(&t);
+                let pattern = if self.matches(&[TokenType::Colon])? {
+                    self.pattern()?
+                } else {
+                    Pattern::Binding(key.clone(), t.line)
+                };
+                keys.push(KeyPattern { key, pattern });
+                if !self.matches(&[TokenType::Comma])? {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBrace, "Expected '}' to end map pattern.")?;
+            return Ok(Pattern::Map(keys, line));
+        }
+        if self.check(TokenType::Number)
+            || self.check(TokenType::Minus)
+            || self.check(TokenType::CharLiteral)
+        {
+            let is_char = self.check(TokenType::CharLiteral);
+            let low = self.pattern_number()?;
+            if self.matches(&[TokenType::DotDot])? {
+                let high = self.pattern_number()?;
+                return Ok(Pattern::Range(low, high, line));
+            }
+            if is_char {
+                return Ok(Pattern::Literal(Literal::Char(
+                    std::char::from_u32(low as u32).unwrap(),
+                    line,
+                )));
+            }
+            return Ok(Pattern::Literal(Literal::Number(low, line)));
+        }
+        if self.matches(&[TokenType::String])? {
+            let t = self.previous();
+            return Ok(Pattern::Literal(Literal::String(
+                self.string_literal(&t),
+                line,
+            )));
+        }
+        if self.matches(&[TokenType::True])? {
+            return Ok(Pattern::Literal(Literal::True(line)));
+        }
+        if self.matches(&[TokenType::False])? {
+            return Ok(Pattern::Literal(Literal::False(line)));
+        }
+        if self.matches(&[TokenType::Nil])? {
+            return Ok(Pattern::Literal(Literal::Nil(line)));
+        }
+        if self.matches(&[TokenType::Identifier])? {
+            let t = self.previous();
+            let name = self.scanner.get_lexeme(&t);
+            if self.matches(&[TokenType::ColonColon])? {
+                let path = self.finish_path(name)?;
+                let mut patterns = Vec::new();
+                if self.matches(&[TokenType::LeftParen])? {
+                    while !self.check(TokenType::RightParen) {
+                        patterns.push(self.pattern()?);
+                        if !self.matches(&[TokenType::Comma])? {
+                            break;
+                        }
+                    }
+                    self.consume(
+                        TokenType::RightParen,
+                        "Expected ')' to end variant pattern.",
+                    )?;
+                }
+                return Ok(Pattern::Variant(VariantPattern { path, patterns }));
+            }
+            if name == "_" {
+                return Ok(Pattern::Wildcard);
+            }
+            return Ok(Pattern::Binding(name, line));
+        }
+        Err(ParserError("Expected pattern.".to_string(), line))
+    }
+
+    // A number (possibly negated) or char literal in a pattern, as an f64.
+    fn pattern_number(&mut self) -> Result<f64> {
+        let negate = self.matches(&[TokenType::Minus])?;
+        let n = if self.matches(&[TokenType::CharLiteral])? {
+            let t = self.previous();
+            f64::from(u32::from(self.char_literal(&t)?))
+        } else {
+            let t = self.consume(TokenType::Number, "Expected number in pattern.")?;
+            self.number_literal(&t)?
+        };
+        Ok(if negate { -n } else { n })
+    }
+
+    fn finish_path(&mut self, base: String) -> Result<Path> {
+        let line = self.previous().line;
+        let name = self.consume(TokenType::Identifier, "Expected name after '::'.")?;
+        let name = self.scanner.get_lexeme(&name);
+        Ok(Path { base, name, line })
+    }
+
+    // An interpolated string alternates string segments (each ending in '{')
+    // with expressions, finishing with an ordinary string token for the rest.
+    fn interpolation(&mut self) -> Result<Expression> {
+        let line = self.previous().line;
+        let mut parts = Vec::new();
+        loop {
+            let t = self.previous();
+            let s = self.string_literal(&t);
+            if !s.is_empty() {
+                parts.push(Expression::Literal(Literal::String(s, t.line)));
+            }
+            if t.token_type == TokenType::String {
+                break;
+            }
+            parts.push(self.delimited_expression()?);
+            if !self.matches(&[TokenType::Interpolation, TokenType::String])? {
+                return Err(ParserError(
+                    "Expected '}' after interpolated expression.".to_string(),
+                    self.peek().line,
+                ));
+            }
+        }
+        Ok(Expression::Interpolation(Interpolation { parts, line }))
+    }
+
+    fn throw_expression(&mut self) -> Result<Expression> {
+        let line = self.previous().line;
+        let value = self.expression()?;
+        Ok(Expression::Throw(Throw {
+            value: Box::new(value),
+            line,
+        }))
+    }
+
+    fn try_expression(&mut self) -> Result<Expression> {
+        let line = self.previous().line;
+        let block = self.block()?;
+        self.consume(TokenType::Catch, "Expected catch after try block.")?;
+        let error_name = self.consume(TokenType::Identifier, "Expected name after catch.")?;
+        let error_name = self.scanner.get_lexeme(&error_name);
+        let catch_block = self.block()?;
+        Ok(Expression::Try(Try {
+            block,
+            error_name,
+            catch_block,
+            line,
+        }))
+    }
+
+    fn lambda(&mut self) -> Result<Expression> {
+        let line = self.previous().line;
+        let args = self.arg_list()?;
+        let block = self.block()?;
+        Ok(Expression::Lambda(Lambda { args, block, line }))
+    }
+
     fn array(&mut self) -> Result<Expression> {
         let line = self.previous().line;
         let mut out = Array {
@@ -769,7 +1380,7 @@ impl Parser {
             if self.check(TokenType::RightBracket) {
                 break;
             }
-            out.initializers.push(self.expression()?);
+            out.initializers.push(self.delimited_expression()?);
             if !self.matches(&[TokenType::Comma])? {
                 break;
             }
@@ -790,13 +1401,13 @@ impl Parser {
             }
             if self.matches(&[TokenType::LeftBracket])? {
                 let line = self.previous().line;
-                let lhs = self.expression()?;
+                let lhs = self.delimited_expression()?;
                 self.consume(
                     TokenType::RightBracket,
                     "Expected ']' after map key expression",
                 )?;
                 self.consume(TokenType::Colon, "Expected ':' in map initializer")?;
-                let value = self.expression()?;
+                let value = self.delimited_expression()?;
                 out.initializers.push(MapInitializer {
                     key: MapLHS::Expression(lhs),
                     value: Box::new(value),
@@ -810,7 +1421,7 @@ impl Parser {
                 let line = self.previous().line;
                 let name = self.scanner.get_lexeme(&name_t);
                 if self.matches(&[TokenType::Colon])? {
-                    let value = self.expression()?;
+                    let value = self.delimited_expression()?;
                     out.initializers.push(MapInitializer {
                         key: MapLHS::Name(name),
                         value: Box::new(value),
@@ -832,6 +1443,44 @@ impl Parser {
         Ok(Expression::Map(out))
     }
 
+    fn struct_literal(&mut self, name: String, line: usize) -> Result<Expression> {
+        self.consume(
+            TokenType::LeftBrace,
+            "Expected '{' to start struct literal.",
+        )?;
+        let mut initializers = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            let name_t = self.consume(
+                TokenType::Identifier,
+                "Expected field name in struct literal",
+            )?;
+            let line = name_t.line;
+            let name = self.scanner.get_lexeme(&name_t);
+            let value = if self.matches(&[TokenType::Colon])? {
+                self.delimited_expression()?
+            } else {
+                Expression::Variable(Variable {
+                    name: name.clone(),
+                    line,
+                })
+            };
+            initializers.push(FieldInitializer {
+                name,
+                value: Box::new(value),
+                line,
+            });
+            if !self.matches(&[TokenType::Comma])? {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' to end struct literal")?;
+        Ok(Expression::StructLiteral(StructLiteral {
+            name,
+            initializers,
+            line,
+        }))
+    }
+
     fn primary(&mut self) -> Result<Expression> {
         if self.peek().token_type == TokenType::LeftBrace {
             return Ok(Expression::Block(self.block()?));
@@ -857,6 +1506,18 @@ impl Parser {
         if self.matches(&[TokenType::Return])? {
             return self.return_expression();
         }
+        if self.matches(&[TokenType::Fn])? {
+            return self.lambda();
+        }
+        if self.matches(&[TokenType::Match])? {
+            return self.match_expression();
+        }
+        if self.matches(&[TokenType::Try])? {
+            return self.try_expression();
+        }
+        if self.matches(&[TokenType::Throw])? {
+            return self.throw_expression();
+        }
         if self.matches(&[TokenType::Break])? {
             return Ok(Expression::Break(self.previous().line));
         }
@@ -874,55 +1535,42 @@ impl Parser {
         }
         if self.matches(&[TokenType::Number])? {
             let t = self.previous();
-            let s = self.scanner.get_lexeme(&t);
-            return match s.parse::<f64>() {
-                Ok(f) => Ok(Expression::Literal(Literal::Number(f, t.line))),
-                Err(_) => Err(ParserError(
-                    "Invalid number literal".to_string(),
-                    self.previous().line,
-                )),
-            };
+            return Ok(Expression::Literal(Literal::Number(
+                self.number_literal(&t)?,
+                t.line,
+            )));
         }
         if self.matches(&[TokenType::String])? {
             let t = self.previous();
-            let s = self.scanner.get_lexeme(&t);
-            let s = &s[1..s.len() - 1];
-            let s = s
-                .replace("\\n", "\n")
-                .replace("\\t", "\t")
-                .replace("\\r", "\r")
-                .replace("\\\\", "\\");
-            return Ok(Expression::Literal(Literal::String(s.to_string(), t.line)));
+            return Ok(Expression::Literal(Literal::String(
+                self.string_literal(&t),
+                t.line,
+            )));
+        }
+        if self.matches(&[TokenType::Interpolation])? {
+            return self.interpolation();
         }
         if self.matches(&[TokenType::CharLiteral])? {
             let t = self.previous();
-            let s = self.scanner.get_lexeme(&t);
-            let chars = s.chars().collect::<Vec<_>>();
-            let mut c = chars[1];
-            if c == '\\' {
-                match chars[2] {
-                    'n' => c = '\n',
-                    'r' => c = '\r',
-                    't' => c = '\t',
-                    '\\' => c = '\\',
-                    _ => {
-                        return Err(ParserError(
-                            "Unknown char literal escape".to_string(),
-                            self.previous().line,
-                        ))
-                    }
-                }
-            }
-            return Ok(Expression::Literal(Literal::Char(c, t.line)));
+            return Ok(Expression::Literal(Literal::Char(
+                self.char_literal(&t)?,
+                t.line,
+            )));
         }
         if self.matches(&[TokenType::Identifier])? {
             let t = self.previous();
             let name = self.scanner.get_lexeme(&t);
+            if self.matches(&[TokenType::ColonColon])? {
+                return Ok(Expression::Path(self.finish_path(name)?));
+            }
+            if self.struct_literals_allowed && self.check(TokenType::LeftBrace) {
+                return self.struct_literal(name, t.line);
+            }
             return Ok(Expression::Variable(Variable { name, line: t.line }));
         }
         if self.matches(&[TokenType::LeftParen])? {
             let line = self.previous().line;
-            let expression = self.expression()?;
+            let expression = self.delimited_expression()?;
             self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
             return Ok(Expression::Grouping(Grouping {
                 expression: Box::new(expression),
@@ -935,6 +1583,64 @@ impl Parser {
         ))
     }
 
+    fn number_literal(&self, t: &scanner::Token) -> Result<f64> {
+        let s = self.scanner.get_lexeme(t);
+        match s.parse::<f64>() {
+            Ok(f) => Ok(f),
+            Err(_) => Err(ParserError("Invalid number literal".to_string(), t.line)),
+        }
+    }
+
+    // Interpolation segments are delimited by '{' or '}' rather than quotes,
+    // but either way there's one character to strip from each end.
+    // Escapes are decoded in one pass, so an escaped backslash never combines
+    // with the character after it. Unknown escapes are kept as written.
+    fn string_literal(&self, t: &scanner::Token) -> String {
+        let s = self.scanner.get_lexeme(t);
+        let s = &s[1..s.len() - 1];
+        let mut out = String::with_capacity(s.len());
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some(c @ ('{' | '}' | '"' | '\\')) => out.push(c),
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => out.push('\\'),
+            }
+        }
+        out
+    }
+
+    fn char_literal(&self, t: &scanner::Token) -> Result<char> {
+        let s = self.scanner.get_lexeme(t);
+        let chars = s.chars().collect::<Vec<_>>();
+        let mut c = chars[1];
+        if c == '\\' {
+            match chars[2] {
+                'n' => c = '\n',
+                'r' => c = '\r',
+                't' => c = '\t',
+                '\\' => c = '\\',
+                _ => {
+                    return Err(ParserError(
+                        "Unknown char literal escape".to_string(),
+                        t.line,
+                    ))
+                }
+            }
+        }
+        Ok(c)
+    }
+
     fn matches(&mut self, types: &[TokenType]) -> Result<bool> {
         for t in types {
             if self.check(*t) {
@@ -981,34 +1687,48 @@ impl Parser {
 }
 
 pub fn parse(source: &str) -> Result<Program> {
-    /*
-    let mut scanner = scanner::Scanner::new(source);
-    let mut line = std::usize::MAX;
-    loop {
-        let token = scanner.scan_token()?;
-        if token.line != line {
-            print!("{:4} ", token.line);
-            line = token.line;
-        } else {
-            print!("   | ");
-        }
-        println!(
-            "{:?} '{}'",
-            token.token_type,
-            &source[token.start..token.start + token.length]
-        );
-
-        if token.token_type == scanner::TokenType::EOF {
-            return Ok(());
-        }
-    }
-     */
     let mut parser = Parser::try_new(source)?;
     let mut statements = Vec::new();
     while !parser.is_at_end() {
         statements.push(parser.statement()?);
     }
-    let out = Program { statements };
-    println!("{:?}", out);
-    Ok(out)
+    Ok(Program { statements })
+}
+
+pub fn parse_repl(source: &str) -> Result<Block> {
+    Parser::try_new(source)?.repl_entry()
+}
+
+// Whether source is a whole REPL entry, rather than the start of one that
+// carries on over following lines because it has unclosed brackets or
+// strings or the parser runs out of input. Input with an error before the
+// end counts as complete, so the error gets reported.
+pub fn is_complete(source: &str) -> bool {
+    let mut scanner = scanner::Scanner::new(source);
+    let mut depth = 0;
+    loop {
+        match scanner.scan_token() {
+            Ok(token) => match token.token_type {
+                TokenType::LeftParen
+                | TokenType::LeftBracket
+                | TokenType::LeftBrace
+                | TokenType::HashLeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                    depth -= 1
+                }
+                TokenType::EOF => break,
+                _ => {}
+            },
+            Err(ScannerError(e)) => return e != "Unterminated string.",
+            Err(_) => return true,
+        }
+    }
+    if depth > 0 {
+        return false;
+    }
+    let mut parser = match Parser::try_new(source) {
+        Ok(parser) => parser,
+        Err(_) => return true,
+    };
+    parser.repl_entry().is_ok() || !parser.is_at_end()
 }
@@ -10,6 +10,8 @@ pub struct Scanner {
     current: usize,
     line: usize,
     kw_map: std::collections::HashMap<String, TokenType>,
+    // Count of unclosed braces inside each string interpolation we're in.
+    interpolations: Vec<usize>,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -26,6 +28,7 @@ pub enum TokenType {
 
     Semicolon,
     Colon,
+    ColonColon,
 
     Minus,
     MinusEqual,
@@ -42,6 +45,7 @@ pub enum TokenType {
     BangEqual,
     Equal,
     EqualEqual,
+    FatArrow,
     Greater,
     GreaterEqual,
     Less,
@@ -52,17 +56,22 @@ pub enum TokenType {
 
     Identifier,
     String,
+    Interpolation,
     Number,
     CharLiteral,
 
     Break,
+    Catch,
     Const,
     Continue,
     Else,
+    Enum,
     False,
     Fn,
     For,
     If,
+    Impl,
+    Import,
     In,
     Nil,
     Print,
@@ -70,6 +79,10 @@ pub enum TokenType {
     True,
     Let,
     Loop,
+    Match,
+    Struct,
+    Throw,
+    Try,
     While,
 
     EOF,
@@ -89,13 +102,17 @@ impl Scanner {
     pub fn new(source: &str) -> Scanner {
         let mut kw_map = std::collections::HashMap::new();
         kw_map.insert("break".to_string(), TokenType::Break);
+        kw_map.insert("catch".to_string(), TokenType::Catch);
         kw_map.insert("const".to_string(), TokenType::Const);
         kw_map.insert("continue".to_string(), TokenType::Continue);
         kw_map.insert("else".to_string(), TokenType::Else);
+        kw_map.insert("enum".to_string(), TokenType::Enum);
         kw_map.insert("false".to_string(), TokenType::False);
         kw_map.insert("for".to_string(), TokenType::For);
         kw_map.insert("fn".to_string(), TokenType::Fn);
         kw_map.insert("if".to_string(), TokenType::If);
+        kw_map.insert("impl".to_string(), TokenType::Impl);
+        kw_map.insert("import".to_string(), TokenType::Import);
         kw_map.insert("in".to_string(), TokenType::In);
         kw_map.insert("nil".to_string(), TokenType::Nil);
         kw_map.insert("print".to_string(), TokenType::Print);
@@ -103,6 +120,10 @@ impl Scanner {
         kw_map.insert("true".to_string(), TokenType::True);
         kw_map.insert("let".to_string(), TokenType::Let);
         kw_map.insert("loop".to_string(), TokenType::Loop);
+        kw_map.insert("match".to_string(), TokenType::Match);
+        kw_map.insert("struct".to_string(), TokenType::Struct);
+        kw_map.insert("throw".to_string(), TokenType::Throw);
+        kw_map.insert("try".to_string(), TokenType::Try);
         kw_map.insert("while".to_string(), TokenType::While);
 
         Scanner {
@@ -111,6 +132,7 @@ impl Scanner {
             current: 0,
             line: 1,
             kw_map,
+            interpolations: Vec::new(),
         }
     }
 
@@ -126,12 +148,36 @@ impl Scanner {
         match c {
             '(' => Ok(self.make_token(TokenType::LeftParen)),
             ')' => Ok(self.make_token(TokenType::RightParen)),
-            '{' => Ok(self.make_token(TokenType::LeftBrace)),
-            '}' => Ok(self.make_token(TokenType::RightBrace)),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                Ok(self.make_token(TokenType::LeftBrace))
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    // This closes an interpolated expression, carry on with
+                    // the rest of the string.
+                    self.interpolations.pop();
+                    self.string()
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    Ok(self.make_token(TokenType::RightBrace))
+                }
+                None => Ok(self.make_token(TokenType::RightBrace)),
+            },
             '[' => Ok(self.make_token(TokenType::LeftBracket)),
             ']' => Ok(self.make_token(TokenType::RightBracket)),
             ';' => Ok(self.make_token(TokenType::Semicolon)),
-            ':' => Ok(self.make_token(TokenType::Colon)),
+            ':' => {
+                let token_type = if self.token_match(':') {
+                    TokenType::ColonColon
+                } else {
+                    TokenType::Colon
+                };
+                Ok(self.make_token(token_type))
+            }
             ',' => Ok(self.make_token(TokenType::Comma)),
             '%' => Ok(self.make_token(TokenType::Percent)),
 
@@ -188,6 +234,8 @@ impl Scanner {
             '=' => {
                 let token_type = if self.token_match('=') {
                     TokenType::EqualEqual
+                } else if self.token_match('>') {
+                    TokenType::FatArrow
                 } else {
                     TokenType::Equal
                 };
@@ -211,6 +259,9 @@ impl Scanner {
             }
             '#' => {
                 if self.token_match('{') {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
                     Ok(self.make_token(TokenType::HashLeftBrace))
                 } else {
                     Err(ScannerError(
@@ -248,16 +299,31 @@ impl Scanner {
         }
     }
 
+    // Scan the next token without consuming it.
+    pub fn peek_token(&mut self) -> Result<Token> {
+        let (start, current, line) = (self.start, self.current, self.line);
+        let interpolations = self.interpolations.clone();
+        let token = self.scan_token();
+        self.start = start;
+        self.current = current;
+        self.line = line;
+        self.interpolations = interpolations;
+        token
+    }
+
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
     fn peek(&self) -> char {
+        if self.is_at_end() {
+            return '\0';
+        }
         self.source[self.current]
     }
 
     fn peek_next(&self) -> char {
-        if self.is_at_end() {
+        if self.current + 1 >= self.source.len() {
             return '\0';
         }
         self.source[self.current + 1]
@@ -313,10 +379,25 @@ impl Scanner {
         }
     }
 
+    // Scans up to the closing quote, or up to a '{' starting an interpolated
+    // expression. In that case the token is an Interpolation and the string
+    // is resumed by the matching '}'.
     fn string(&mut self) -> Result<Token> {
-        while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+        while !self.is_at_end() && self.peek() != '"' {
+            match self.peek() {
+                '\n' => self.line += 1,
+                '\\' => {
+                    self.advance();
+                    if self.is_at_end() {
+                        break;
+                    }
+                }
+                '{' => {
+                    self.advance();
+                    self.interpolations.push(0);
+                    return Ok(self.make_token(TokenType::Interpolation));
+                }
+                _ => {}
             }
             self.advance();
         }
@@ -12,6 +12,9 @@ pub enum Value {
     Range(f64, f64),
     MapForContext(Vec<HashableValue>, f64, f64),
     Callable(usize),
+    // A function registered by the host program, numbered in order of
+    // registration with the VM.
+    NativeFunction(usize),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -28,6 +31,7 @@ impl SanitizedFloat {
             Err(InterpreterError::RuntimeError(
                 "Tried to hash bad float.".to_string(),
                 line,
+                Vec::new(),
             ))
         } else {
             let (mantissa, exponent, sign) = value.integer_decode();
@@ -56,6 +60,7 @@ pub enum HashableValue {
     ReferenceId(usize),
     Range(SanitizedFloat, SanitizedFloat),
     Callable(usize),
+    NativeFunction(usize),
 }
 
 // TL;DR Different enum cases always compare less/equal based on their order in the enum.
@@ -94,21 +99,29 @@ impl Ord for HashableValue {
                 HashableValue::String(s2) => s.cmp(s2),
                 HashableValue::ReferenceId(_)
                 | HashableValue::Range(..)
-                | HashableValue::Callable(..) => Ordering::Less,
+                | HashableValue::Callable(..)
+                | HashableValue::NativeFunction(..) => Ordering::Less,
                 _ => Ordering::Greater,
             },
             HashableValue::ReferenceId(id) => match other {
                 HashableValue::ReferenceId(id2) => id.cmp(id2),
-                HashableValue::Range(..) | HashableValue::Callable(..) => Ordering::Less,
+                HashableValue::Range(..)
+                | HashableValue::Callable(..)
+                | HashableValue::NativeFunction(..) => Ordering::Less,
                 _ => Ordering::Greater,
             },
             HashableValue::Range(l, _) => match other {
                 HashableValue::Range(l2, _) => l.to_f64().partial_cmp(&l2.to_f64()).unwrap(),
-                HashableValue::Callable(..) => Ordering::Less,
+                HashableValue::Callable(..) | HashableValue::NativeFunction(..) => Ordering::Less,
                 _ => Ordering::Greater,
             },
             HashableValue::Callable(c) => match other {
                 HashableValue::Callable(c2) => c.cmp(c2),
+                HashableValue::NativeFunction(..) => Ordering::Less,
+                _ => Ordering::Greater,
+            },
+            HashableValue::NativeFunction(n) => match other {
+                HashableValue::NativeFunction(n2) => n.cmp(n2),
                 _ => Ordering::Greater,
             },
         }
@@ -136,8 +149,10 @@ impl HashableValue {
             Value::MapForContext(..) => Err(InterpreterError::RuntimeError(
                 "Tried to hash map for context, this should never happen.".to_string(),
                 line,
+                Vec::new(),
             )),
             Value::Callable(c) => Ok(HashableValue::Callable(*c)),
+            Value::NativeFunction(n) => Ok(HashableValue::NativeFunction(*n)),
         }
     }
 }
@@ -152,51 +167,121 @@ impl Value {
             HashableValue::ReferenceId(i) => Value::ReferenceId(*i),
             HashableValue::Range(l, r) => Value::Range(l.to_f64(), r.to_f64()),
             HashableValue::Callable(c) => Value::Callable(*c),
+            HashableValue::NativeFunction(n) => Value::NativeFunction(*n),
         }
     }
 }
 
+// A Rust object exposed to scripts, whose methods are called with the usual
+// obj:method(args) syntax. Errors are reported at the line of the call, so
+// implementations can leave the line as 0.
 pub trait ExternalType {
-    fn get_arity(&self, name: &str) -> usize;
-    fn call(&mut self, name: &str, args: Vec<Value>) -> ValueOrRef;
+    // Name used in error messages and when the object is printed.
+    fn type_name(&self) -> &'static str;
+    fn methods(&self) -> &'static [&'static str];
+    fn get_arity(&self, name: &str) -> Result<usize, InterpreterError>;
+    // args are in the order they were passed.
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Result<ValueOrRef, InterpreterError>;
+    // Every value the object holds on to. Implementations that keep any
+    // ReferenceIds must return them, or the garbage collector will free
+    // what they refer to.
+    fn trace(&self) -> Vec<Value> {
+        Vec::new()
+    }
+}
+
+pub fn no_such_method(external: &dyn ExternalType, name: &str) -> InterpreterError {
+    InterpreterError::RuntimeError(
+        format!(
+            "{} has no method {} (methods: {})",
+            external.type_name(),
+            name,
+            external.methods().join(", ")
+        ),
+        0,
+        Vec::new(),
+    )
 }
 
 use regex::Regex;
 impl ExternalType for Regex {
-    fn get_arity(&self, name: &str) -> usize {
+    fn type_name(&self) -> &'static str {
+        "Regex"
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["match"]
+    }
+
+    fn get_arity(&self, name: &str) -> Result<usize, InterpreterError> {
         if name == "match" {
-            1
+            Ok(1)
         } else {
-            panic!("Bad call to regex.")
+            Err(no_such_method(self, name))
         }
     }
 
-    fn call(&mut self, name: &str, args: Vec<Value>) -> ValueOrRef {
+    // match returns an array of the whole match followed by each capture
+    // group (nil for groups that didn't participate), or nil if no match.
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Result<ValueOrRef, InterpreterError> {
         if name == "match" {
             if let Value::String(ref s) = args[0] {
                 match self.captures(&s) {
-                    Some(c) => {
-                        return ValueOrRef::Ref(ReferenceType::Array(
-                            c.iter()
-                                .map(|e| Value::String(e.unwrap().as_str().to_string()))
-                                .collect(),
-                        ))
-                    }
-                    None => return ValueOrRef::Value(Value::Nil),
+                    Some(c) => Ok(ValueOrRef::Ref(ReferenceType::Array(
+                        c.iter()
+                            .map(|e| match e {
+                                Some(e) => Value::String(e.as_str().to_string()),
+                                None => Value::Nil,
+                            })
+                            .collect(),
+                    ))),
+                    None => Ok(ValueOrRef::Value(Value::Nil)),
                 }
             } else {
-                panic!("Bad call to regex match");
+                Err(InterpreterError::RuntimeError(
+                    "Expected string argument to Regex match".to_string(),
+                    0,
+                    Vec::new(),
+                ))
             }
         } else {
-            panic!("Bad call to regex.")
+            Err(no_such_method(self, name))
         }
     }
 }
 
+pub struct Closure {
+    pub function: usize,
+    pub upvalues: Vec<usize>,
+}
+
+// An upvalue is open while the captured local is still live in the VM's
+// locals (the usize is the absolute locals slot), and closed once the
+// owning scope has exited and the value has been moved into the upvalue.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct Instance {
+    pub struct_id: usize,
+    pub fields: Vec<Value>,
+}
+
+pub struct EnumValue {
+    pub enum_id: usize,
+    pub variant: usize,
+    pub payload: Vec<Value>,
+}
+
 pub enum ReferenceType {
     Array(Vec<Value>),
     Map(HashMap<HashableValue, Value>),
     External(Box<dyn ExternalType>),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Instance(Instance),
+    Enum(EnumValue),
 }
 
 impl std::fmt::Display for Value {
@@ -210,6 +295,7 @@ impl std::fmt::Display for Value {
             Value::Range(l, r) => write!(f, "{}..{}", l, r),
             Value::MapForContext(..) => panic!("Attempted to display map for context."),
             Value::Callable(c) => write!(f, "Callable({})", c),
+            Value::NativeFunction(n) => write!(f, "NativeFunction({})", n),
         }
     }
 }
@@ -1,16 +1,40 @@
 use super::chunk::OpCode;
-use super::{chunk, compiler, debug, errors::NotloxError, value::*};
+use super::convert::{FromNail, IntoNail};
+use super::{chunk, compiler, convert, debug, errors::NotloxError, gc::Heap, value::*};
 use std::collections::HashMap;
+use std::path::Path;
 use std::time::Instant;
 
+// Initial sizes of the stacks, which grow as needed up to the limits in
+// StackLimits.
 const STACK_SIZE: usize = 256;
 
 #[derive(Copy, Clone, Debug)]
 struct CallFrame {
     return_address: usize,
     locals_base: usize,
+    closure: Option<usize>,
+    function: usize,
 }
 
+// State to unwind to when an error is thrown inside a try block.
+#[derive(Copy, Clone, Debug)]
+struct Handler {
+    catch_address: usize,
+    first_local: usize,
+    stack_top: usize,
+    return_stack_top: usize,
+    locals_base: usize,
+    locals_top: usize,
+    closure: Option<usize>,
+    function: usize,
+}
+
+// A value held by the host program. Anything it references is kept alive
+// by the garbage collector until the handle is released.
+#[derive(Debug)]
+pub struct Handle(usize);
+
 struct ValueStack {
     stack: Vec<Value>,
     top: usize,
@@ -27,7 +51,11 @@ impl ValueStack {
     }
 
     fn push(&mut self, value: Value) {
-        self.stack[self.top] = value;
+        if self.top < self.stack.len() {
+            self.stack[self.top] = value;
+        } else {
+            self.stack.push(value);
+        }
         self.top += 1
     }
 
@@ -58,22 +86,84 @@ impl ValueStack {
     }
 }
 
+// Diagnostics for the VM to print, all off by default, and limits on how
+// far a program can recurse.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct VmOptions {
+    pub compile: compiler::CompileOptions,
+    pub timing: bool,
+    pub limits: StackLimits,
+}
+
+// Exceeding any of these is a stack overflow runtime error. The value stack
+// is checked after each instruction, so it can go over by the few values one
+// instruction pushes.
+#[derive(Copy, Clone, Debug)]
+pub struct StackLimits {
+    pub value_stack: usize,
+    pub locals: usize,
+    pub call_depth: usize,
+}
+
+impl Default for StackLimits {
+    fn default() -> Self {
+        StackLimits {
+            value_stack: 1 << 20,
+            locals: 1 << 20,
+            call_depth: 1 << 16,
+        }
+    }
+}
+
+type NativeFn = dyn Fn(&mut Heap, &[Value]) -> Result<Value, InterpreterError>;
+
+struct Native {
+    name: String,
+    arity: u8,
+    function: Box<NativeFn>,
+}
+
 pub struct VM {
     chunk: chunk::Chunk,
     ip: usize,
     stack: ValueStack,
-    return_stack: [CallFrame; STACK_SIZE],
+    return_stack: Vec<CallFrame>,
     return_stack_top: usize,
     locals: Vec<Value>,
     locals_base: usize,
     locals_top: usize,
-    heap: Vec<ReferenceType>,
+    closure: Option<usize>,
+    // Entry address of the running function.
+    function: usize,
+    open_upvalues: Vec<(usize, usize)>,
+    handlers: Vec<Handler>,
+    heap: Heap,
+    session: compiler::Session,
+    options: VmOptions,
+    args: Vec<String>,
+    natives: Vec<Native>,
+    handles: Vec<Option<Value>>,
+}
+
+// One call in a runtime error's backtrace: the function and the line it
+// had reached.
+#[derive(Clone, Debug)]
+pub struct TraceFrame {
+    pub function: String,
+    pub line: usize,
 }
 
 #[derive(Debug)]
 pub enum InterpreterError {
     CompileError(NotloxError),
-    RuntimeError(String, usize),
+    // Message, line and backtrace, outermost call first. The backtrace is
+    // filled in by the VM when the error isn't caught, so host code creating
+    // one can leave it empty.
+    RuntimeError(String, usize, Vec<TraceFrame>),
+    // The program called exit with this status. Not catchable.
+    Exit(i32),
+    // The host called a function the loaded program doesn't define.
+    UndefinedFunction(String),
 }
 
 impl From<NotloxError> for InterpreterError {
@@ -86,9 +176,32 @@ impl std::fmt::Display for InterpreterError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             InterpreterError::CompileError(c) => c.fmt(f),
-            InterpreterError::RuntimeError(s, line) => {
-                write!(f, "Runtime Error, line {}: {}", line, s)
+            InterpreterError::RuntimeError(s, line, trace) => {
+                write!(f, "Runtime Error, line {}: {}", line, s)?;
+                if !trace.is_empty() {
+                    write!(f, "\nTraceback (most recent call last):")?;
+                }
+                // Collapse runs of the same frame, as from deep recursion.
+                let mut i = 0;
+                while i < trace.len() {
+                    let frame = &trace[i];
+                    write!(f, "\n  in {}, line {}", frame.function, frame.line)?;
+                    let mut repeats = 0;
+                    while i + repeats + 1 < trace.len()
+                        && trace[i + repeats + 1].function == frame.function
+                        && trace[i + repeats + 1].line == frame.line
+                    {
+                        repeats += 1;
+                    }
+                    if repeats > 0 {
+                        write!(f, "\n  ... repeated {} more times", repeats)?;
+                    }
+                    i += repeats + 1;
+                }
+                Ok(())
             }
+            InterpreterError::Exit(status) => write!(f, "Exited with status {}", status),
+            InterpreterError::UndefinedFunction(name) => write!(f, "Undefined function: {}", name),
         }
     }
 }
@@ -96,7 +209,33 @@ impl std::fmt::Display for InterpreterError {
 impl std::error::Error for InterpreterError {}
 
 fn runtime_error<T>(message: &str, line: usize) -> Result<T, InterpreterError> {
-    Err(InterpreterError::RuntimeError(message.to_string(), line))
+    Err(InterpreterError::RuntimeError(
+        message.to_string(),
+        line,
+        Vec::new(),
+    ))
+}
+
+// Only the low byte of a status reaches the parent process, so anything
+// else is an error rather than silently changed.
+pub fn exit_status(n: f64) -> Option<i32> {
+    if n.fract() == 0.0 && (0.0..=255.0).contains(&n) {
+        Some(n as i32)
+    } else {
+        None
+    }
+}
+
+pub fn bad_exit_status(n: f64) -> String {
+    format!("Exit status must be an integer from 0 to 255, got {}", n)
+}
+
+// Report a runtime error from host code at the given line.
+fn with_line<T>(result: Result<T, InterpreterError>, line: usize) -> Result<T, InterpreterError> {
+    match result {
+        Err(InterpreterError::RuntimeError(message, _, _)) => runtime_error(&message, line),
+        result => result,
+    }
 }
 
 macro_rules! binary_op {
@@ -127,6 +266,10 @@ impl Default for VM {
 
 impl VM {
     pub fn new() -> VM {
+        Self::with_options(VmOptions::default())
+    }
+
+    pub fn with_options(options: VmOptions) -> VM {
         let mut array = Vec::new();
         array.resize(STACK_SIZE, Value::Nil);
 
@@ -134,37 +277,350 @@ impl VM {
             chunk: chunk::Chunk::new(),
             ip: 0,
             stack: ValueStack::new(),
-            return_stack: [CallFrame {
-                return_address: 0,
-                locals_base: 0,
-            }; STACK_SIZE],
+            return_stack: Vec::with_capacity(STACK_SIZE),
             return_stack_top: 0,
             locals: array,
             locals_base: 0,
             locals_top: 0,
-            heap: Vec::new(),
+            closure: None,
+            function: 0,
+            open_upvalues: Vec::new(),
+            handlers: Vec::new(),
+            heap: Heap::new(),
+            session: compiler::Session::new(),
+            options,
+            args: Vec::new(),
+            natives: Vec::new(),
+            handles: Vec::new(),
         }
     }
 
     pub fn interpret(&mut self, source: &str) -> Result<Value, InterpreterError> {
+        self.interpret_source(source, None)
+    }
+
+    // As interpret, but imports are resolved relative to path.
+    pub fn interpret_file(&mut self, source: &str, path: &Path) -> Result<Value, InterpreterError> {
+        self.interpret_source(source, Some(path))
+    }
+
+    fn interpret_source(
+        &mut self,
+        source: &str,
+        path: Option<&Path>,
+    ) -> Result<Value, InterpreterError> {
         let start = Instant::now();
-        let chunk = compiler::compile(source)?;
+        self.load_source(source, path)?;
         let compiled = Instant::now();
-        self.chunk = chunk;
-        self.ip = self.chunk.lookup_function("main");
+        self.reset();
+        let main = match self.chunk.function_names.get("main") {
+            Some(n) => *n,
+            None => {
+                return Err(InterpreterError::CompileError(NotloxError::CompilerError(
+                    "No main function defined".to_string(),
+                )))
+            }
+        };
+        match self.chunk.function_arities[main] {
+            0 => {}
+            1 => {
+                let args = self.args.iter().cloned().map(Value::String).collect();
+                let id = self.new_reference_type(ReferenceType::Array(args));
+                self.stack.push(Value::ReferenceId(id));
+            }
+            _ => {
+                return Err(InterpreterError::CompileError(NotloxError::CompilerError(
+                    "main must take no arguments, or one for the command line arguments"
+                        .to_string(),
+                )))
+            }
+        }
+        self.ip = self.chunk.function_locations[main];
         let result = self.run();
         let finished = Instant::now();
-        println!(
-            "VM Done. Compiled: {}s {}ms, Run: {}s {}ms.",
-            compiled.duration_since(start).as_secs(),
-            compiled.duration_since(start).subsec_millis(),
-            finished.duration_since(compiled).as_secs(),
-            finished.duration_since(compiled).subsec_millis()
-        );
+        if self.options.timing {
+            eprintln!(
+                "VM Done. Compiled: {}s {}ms, Run: {}s {}ms.",
+                compiled.duration_since(start).as_secs(),
+                compiled.duration_since(start).subsec_millis(),
+                finished.duration_since(compiled).as_secs(),
+                finished.duration_since(compiled).subsec_millis()
+            );
+        }
         result
     }
 
+    // Compile source, replacing the current program, without running it.
+    // Its functions can then be run with call.
+    pub fn load(&mut self, source: &str) -> Result<(), InterpreterError> {
+        self.load_source(source, None)
+    }
+
+    // As load, but imports are resolved relative to path.
+    pub fn load_file(&mut self, source: &str, path: &Path) -> Result<(), InterpreterError> {
+        self.load_source(source, Some(path))
+    }
+
+    fn load_source(&mut self, source: &str, path: Option<&Path>) -> Result<(), InterpreterError> {
+        self.chunk = compiler::compile(source, path, &self.options.compile, &self.native_names())?;
+        self.session = compiler::Session::new();
+        Ok(())
+    }
+
+    // Run the function called name in the loaded program with args, returning
+    // a handle to its result, which stays alive until released. Functions
+    // from imported modules are named like grid.size.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Handle, InterpreterError> {
+        let function = match self.chunk.function_names.get(name) {
+            Some(n) => *n,
+            None => return Err(InterpreterError::UndefinedFunction(name.to_string())),
+        };
+        let address = self.chunk.function_locations[function];
+        let arity = self.chunk.function_arities[function] as usize;
+        if args.len() != arity {
+            return runtime_error(
+                &format!(
+                    "Function {} expects {} arguments but was called with {}.",
+                    name,
+                    arity,
+                    args.len()
+                ),
+                self.chunk.lines[address],
+            );
+        }
+        self.reset();
+        for arg in args {
+            self.stack.push(arg.clone());
+        }
+        self.ip = address;
+        let result = self.run()?;
+        Ok(self.handle(result))
+    }
+
+    // Run source as an entry typed at the REPL. Unlike interpret, the chunk,
+    // globals and heap are kept, so definitions from earlier entries can be
+    // used. Returns the value of the entry's final expression, if any.
+    pub fn interpret_repl(&mut self, source: &str) -> Result<Value, InterpreterError> {
+        self.interpret_repl_source(source, None)
+    }
+
+    // As interpret_repl, for a file loaded into the REPL session.
+    pub fn interpret_repl_file(
+        &mut self,
+        source: &str,
+        path: &Path,
+    ) -> Result<Value, InterpreterError> {
+        self.interpret_repl_source(source, Some(path))
+    }
+
+    fn interpret_repl_source(
+        &mut self,
+        source: &str,
+        path: Option<&Path>,
+    ) -> Result<Value, InterpreterError> {
+        let natives = self.native_names();
+        self.ip = self.session.compile(
+            source,
+            path,
+            &mut self.chunk,
+            &self.options.compile,
+            &natives,
+        )?;
+        self.reset();
+        self.run()
+    }
+
+    // Make a Rust function callable from scripts compiled after this, as a
+    // function with the given name. Functions defined by scripts take
+    // precedence over natives of the same name. Any runtime error returned
+    // is reported at the line of the call.
+    pub fn register_fn<F>(&mut self, name: &str, arity: u8, function: F)
+    where
+        F: Fn(&mut Heap, &[Value]) -> Result<Value, InterpreterError> + 'static,
+    {
+        let native = Native {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        };
+        match self.natives.iter().position(|n| n.name == name) {
+            Some(n) => self.natives[n] = native,
+            None => self.natives.push(native),
+        }
+    }
+
+    fn native_names(&self) -> Vec<String> {
+        self.natives.iter().map(|n| n.name.clone()).collect()
+    }
+
+    // The command line arguments passed to main, if it takes a parameter.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn chunk(&self) -> &chunk::Chunk {
+        &self.chunk
+    }
+
+    // Clear out anything left over from a previous run that stopped with an
+    // error part way through.
+    fn reset(&mut self) {
+        self.close_upvalues(0);
+        self.stack.top = 0;
+        self.return_stack_top = 0;
+        self.locals_base = 0;
+        self.locals_top = 0;
+        self.closure = None;
+        self.handlers.clear();
+    }
+
+    // Convert a Rust value, allocating any arrays or maps it needs. The result
+    // isn't rooted, so pass it straight to call or hold it in a handle.
+    pub fn to_value<T: IntoNail>(&mut self, value: T) -> Result<Value, InterpreterError> {
+        value.into_nail(&mut self.heap)
+    }
+
+    pub fn from_value<T: FromNail>(&self, value: &Value) -> Result<T, InterpreterError> {
+        T::from_nail(value, &self.heap)
+    }
+
+    pub fn handle(&mut self, value: Value) -> Handle {
+        match self.handles.iter().position(Option::is_none) {
+            Some(n) => {
+                self.handles[n] = Some(value);
+                Handle(n)
+            }
+            None => {
+                self.handles.push(Some(value));
+                Handle(self.handles.len() - 1)
+            }
+        }
+    }
+
+    pub fn handle_value(&self, handle: &Handle) -> &Value {
+        self.handles[handle.0]
+            .as_ref()
+            .expect("Handle used after release.")
+    }
+
+    pub fn get<T: FromNail>(&self, handle: &Handle) -> Result<T, InterpreterError> {
+        self.from_value(self.handle_value(handle))
+    }
+
+    // Stop keeping the value alive, returning it.
+    pub fn release(&mut self, handle: Handle) -> Value {
+        self.handles[handle.0]
+            .take()
+            .expect("Handle used after release.")
+    }
+
+    pub fn set_gc_threshold(&mut self, threshold: usize) {
+        self.heap.set_threshold(threshold);
+    }
+
+    pub fn live_objects(&self) -> usize {
+        self.heap.live_objects()
+    }
+
+    pub fn collect_garbage(&mut self) -> usize {
+        for v in &self.stack.stack[..self.stack.top] {
+            self.heap.mark_value(v);
+        }
+        for v in &self.locals[..self.locals_top] {
+            self.heap.mark_value(v);
+        }
+        for v in self.chunk.globals.values() {
+            self.heap.mark_value(v);
+        }
+        for v in &self.chunk.constants {
+            self.heap.mark_value(v);
+        }
+        for v in self.handles.iter().flatten() {
+            self.heap.mark_value(v);
+        }
+        for frame in &self.return_stack[..self.return_stack_top] {
+            if let Some(id) = frame.closure {
+                self.heap.mark_object(id);
+            }
+        }
+        if let Some(id) = self.closure {
+            self.heap.mark_object(id);
+        }
+        for (_, id) in &self.open_upvalues {
+            self.heap.mark_object(*id);
+        }
+        self.heap.trace();
+        let freed = self.heap.sweep();
+
+        // Locals above the top are dead but may be picked up by a later call
+        // before they're assigned, so don't leave freed ids lying around.
+        for v in &mut self.locals[self.locals_top..] {
+            *v = Value::Nil;
+        }
+        freed
+    }
+
+    // Runtime errors are caught here and passed to the innermost try block's
+    // handler as a map with the message and line, if there is one. Otherwise
+    // they're returned with a backtrace of the calls that led to them.
     pub fn run(&mut self) -> Result<Value, InterpreterError> {
+        loop {
+            match self.execute() {
+                Err(InterpreterError::RuntimeError(message, line, _))
+                    if !self.handlers.is_empty() =>
+                {
+                    self.unwind();
+                    let mut error = HashMap::new();
+                    error.insert(
+                        HashableValue::String("message".to_string()),
+                        Value::String(message),
+                    );
+                    error.insert(
+                        HashableValue::String("line".to_string()),
+                        Value::Number(line as f64),
+                    );
+                    let id = self.new_reference_type(ReferenceType::Map(error));
+                    self.stack.push(Value::ReferenceId(id));
+                }
+                Err(InterpreterError::RuntimeError(message, line, _)) => {
+                    let trace = self.backtrace(line);
+                    return Err(InterpreterError::RuntimeError(message, line, trace));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Each caller's line is that of its call instruction, just before the
+    // return address.
+    fn backtrace(&self, line: usize) -> Vec<TraceFrame> {
+        let mut trace: Vec<TraceFrame> = self.return_stack[..self.return_stack_top]
+            .iter()
+            .map(|frame| TraceFrame {
+                function: self.function_name_at(frame.function).to_string(),
+                line: self.chunk.lines[frame.return_address - 1],
+            })
+            .collect();
+        trace.push(TraceFrame {
+            function: self.function_name_at(self.function).to_string(),
+            line,
+        });
+        trace
+    }
+
+    fn function_name_at(&self, entry: usize) -> &str {
+        match self
+            .chunk
+            .function_locations
+            .iter()
+            .position(|l| *l == entry)
+        {
+            Some(number) => self.chunk.function_name(number),
+            None => "<unknown>",
+        }
+    }
+
+    fn execute(&mut self) -> Result<Value, InterpreterError> {
         loop {
             if cfg!(feature = "debugTraceExecution") {
                 print!("          ");
@@ -181,18 +637,34 @@ impl VM {
             let instruction = self.read_byte();
             match OpCode::try_from(instruction) {
                 Some(OpCode::Return) => {
+                    self.close_upvalues(self.locals_base);
+                    while let Some(handler) = self.handlers.last() {
+                        if handler.return_stack_top < self.return_stack_top {
+                            break;
+                        }
+                        self.handlers.pop();
+                    }
                     if self.return_stack_top > 0 {
                         let call_frame = self.return_stack[self.return_stack_top - 1];
                         self.return_stack_top -= 1;
                         self.locals_top = self.locals_base;
                         self.locals_base = call_frame.locals_base;
+                        self.closure = call_frame.closure;
+                        self.function = call_frame.function;
                         self.ip = call_frame.return_address;
                     } else {
+                        // Nothing is running now, so the locals are dead.
+                        self.locals_top = self.locals_base;
                         return Ok(self.stack.pop(line)?);
                     }
                 }
 
                 Some(OpCode::Constant) => self.op_constant(line)?,
+                Some(OpCode::ConstantLong) => {
+                    let constant_number = self.read_long();
+                    let constant = self.chunk.constants[constant_number].clone();
+                    self.stack.push(constant);
+                }
 
                 Some(OpCode::Negate) => self.op_negate(line)?,
 
@@ -202,10 +674,27 @@ impl VM {
                 Some(OpCode::Divide) => binary_op!(self, /, Number, Number, line),
                 Some(OpCode::Remainder) => binary_op!(self, %, Number, Number, line),
 
-                Some(OpCode::Print) => println!("{}", self.stack.pop(line)?),
+                Some(OpCode::Print) => {
+                    let value = self.stack.pop(line)?;
+                    println!("{}", self.display_value(&value));
+                }
 
-                Some(OpCode::AssignLocal) => self.op_assign_local(line)?,
-                Some(OpCode::LoadLocal) => self.op_load_local(line)?,
+                Some(OpCode::AssignLocal) => {
+                    let number = self.read_byte() as usize;
+                    self.op_assign_local(number, line)?
+                }
+                Some(OpCode::LoadLocal) => {
+                    let number = self.read_byte() as usize;
+                    self.op_load_local(number, line)?
+                }
+                Some(OpCode::AssignLocalLong) => {
+                    let number = self.read_short();
+                    self.op_assign_local(number, line)?
+                }
+                Some(OpCode::LoadLocalLong) => {
+                    let number = self.read_short();
+                    self.op_load_local(number, line)?
+                }
 
                 Some(OpCode::PushNil) => self.stack.push(Value::Nil),
 
@@ -216,8 +705,10 @@ impl VM {
                 Some(OpCode::FunctionEntry) => self.op_function_entry(line)?,
                 Some(OpCode::Call) => self.op_call(line)?,
 
-                Some(OpCode::JumpIfFalse) => self.op_jump_if_false(line)?,
-                Some(OpCode::Jump) => self.op_jump(line)?,
+                Some(OpCode::JumpIfFalse) => self.op_jump_if_false(line, false)?,
+                Some(OpCode::Jump) => self.op_jump(false),
+                Some(OpCode::JumpIfFalseLong) => self.op_jump_if_false(line, true)?,
+                Some(OpCode::JumpLong) => self.op_jump(true),
 
                 Some(OpCode::TestLess) => binary_op!(self, <, Number, Boolean, line),
                 Some(OpCode::TestLessOrEqual) => binary_op!(self, <=, Number, Boolean, line),
@@ -228,12 +719,14 @@ impl VM {
                 Some(OpCode::TestEqual) => {
                     let a = self.stack.pop(line)?;
                     let b = self.stack.pop(line)?;
-                    self.stack.push(Value::Boolean(a == b));
+                    let equal = self.values_equal(&a, &b);
+                    self.stack.push(Value::Boolean(equal));
                 }
                 Some(OpCode::TestNotEqual) => {
                     let a = self.stack.pop(line)?;
                     let b = self.stack.pop(line)?;
-                    self.stack.push(Value::Boolean(a != b));
+                    let equal = self.values_equal(&a, &b);
+                    self.stack.push(Value::Boolean(!equal));
                 }
 
                 Some(OpCode::Index) => self.op_index(line)?,
@@ -251,7 +744,8 @@ impl VM {
 
                 Some(OpCode::MakeRange) => self.op_make_range(line)?,
 
-                Some(OpCode::ForLoop) => self.op_for_loop(line)?,
+                Some(OpCode::ForLoop) => self.op_for_loop(line, false)?,
+                Some(OpCode::ForLoopLong) => self.op_for_loop(line, true)?,
 
                 Some(OpCode::PopMulti) => {
                     let n = self.read_byte();
@@ -279,13 +773,177 @@ impl VM {
                     self.stack.push(val);
                 }
 
-                Some(OpCode::JumpIfTrue) => self.op_jump_if_true(line)?,
+                Some(OpCode::JumpIfTrue) => self.op_jump_if_true(line, false)?,
+                Some(OpCode::JumpIfTrueLong) => self.op_jump_if_true(line, true)?,
 
                 Some(OpCode::AssignGlobal) => self.op_assign_global(line)?,
                 Some(OpCode::LoadGlobal) => self.op_load_global(line)?,
 
+                Some(OpCode::Closure) => self.op_closure(line)?,
+                Some(OpCode::LoadUpvalue) => self.op_load_upvalue(line)?,
+                Some(OpCode::AssignUpvalue) => self.op_assign_upvalue(line)?,
+                Some(OpCode::NewInstance) => {
+                    let struct_id = self.read_byte() as usize;
+                    let fields = vec![Value::Nil; self.chunk.structs[struct_id].fields.len()];
+                    let id = self.new_reference_type(ReferenceType::Instance(Instance {
+                        struct_id,
+                        fields,
+                    }));
+                    self.stack.push(Value::ReferenceId(id));
+                }
+                Some(OpCode::InitField) => self.op_init_field(line)?,
+                Some(OpCode::GetField) => self.op_get_field(line)?,
+                Some(OpCode::SetField) => self.op_set_field(line)?,
+
+                Some(OpCode::CloseUpvalues) => {
+                    let slot = self.read_short() + self.locals_base;
+                    self.close_upvalues(slot);
+                }
+
+                Some(OpCode::NewEnum) => self.op_new_enum(line)?,
+                Some(OpCode::MatchVariant) => {
+                    let enum_id = self.read_byte() as usize;
+                    let variant = self.read_byte() as usize;
+                    let value = self.stack.pop(line)?;
+                    let matched = match value {
+                        Value::ReferenceId(id) => match &self.heap[id] {
+                            ReferenceType::Enum(e) => e.enum_id == enum_id && e.variant == variant,
+                            _ => false,
+                        },
+                        _ => false,
+                    };
+                    self.stack.push(Value::Boolean(matched));
+                }
+                Some(OpCode::VariantPayload) => {
+                    let index = self.read_byte() as usize;
+                    let value = self.stack.pop(line)?;
+                    match value {
+                        Value::ReferenceId(id) => match &self.heap[id] {
+                            ReferenceType::Enum(e) => {
+                                let v = e.payload[index].clone();
+                                self.stack.push(v);
+                            }
+                            _ => return runtime_error("Payload access on non-enum", line),
+                        },
+                        _ => return runtime_error("Payload access on non-enum", line),
+                    }
+                }
+                Some(OpCode::MatchArray) => {
+                    let len = self.read_byte() as usize;
+                    let value = self.stack.pop(line)?;
+                    let matched = match value {
+                        Value::ReferenceId(id) => match &self.heap[id] {
+                            ReferenceType::Array(a) => a.len() == len,
+                            _ => false,
+                        },
+                        _ => false,
+                    };
+                    self.stack.push(Value::Boolean(matched));
+                }
+                Some(OpCode::MatchRange) => {
+                    let range = self.stack.pop(line)?;
+                    let value = self.stack.pop(line)?;
+                    let matched = match (value, range) {
+                        (Value::Number(n), Value::Range(l, r)) => l <= n && n < r,
+                        _ => false,
+                    };
+                    self.stack.push(Value::Boolean(matched));
+                }
+                Some(OpCode::MatchArrayAtLeast) => {
+                    let len = self.read_byte() as usize;
+                    let value = self.stack.pop(line)?;
+                    let matched = match value {
+                        Value::ReferenceId(id) => match &self.heap[id] {
+                            ReferenceType::Array(a) => a.len() >= len,
+                            _ => false,
+                        },
+                        _ => false,
+                    };
+                    self.stack.push(Value::Boolean(matched));
+                }
+                Some(OpCode::ArrayRest) => {
+                    let start = self.read_byte() as usize;
+                    let value = self.stack.pop(line)?;
+                    let rest = match value {
+                        Value::ReferenceId(id) => match &self.heap[id] {
+                            ReferenceType::Array(a) => a[start..].to_vec(),
+                            _ => return runtime_error("Array rest of non-array", line),
+                        },
+                        _ => return runtime_error("Array rest of non-array", line),
+                    };
+                    let id = self.new_reference_type(ReferenceType::Array(rest));
+                    self.stack.push(Value::ReferenceId(id));
+                }
+                Some(OpCode::MatchKey) => {
+                    let key = self.stack.pop(line)?;
+                    let value = self.stack.pop(line)?;
+                    let matched = self.lookup_key(&value, key).is_some();
+                    self.stack.push(Value::Boolean(matched));
+                }
+                Some(OpCode::GetKey) => {
+                    let key = self.stack.pop(line)?;
+                    let value = self.stack.pop(line)?;
+                    let v = self.lookup_key(&value, key).unwrap_or(Value::Nil);
+                    self.stack.push(v);
+                }
+                Some(OpCode::PushHandler) => self.op_push_handler(false),
+                Some(OpCode::PushHandlerLong) => self.op_push_handler(true),
+                Some(OpCode::PopHandler) => {
+                    self.handlers.pop();
+                }
+                Some(OpCode::Throw) => {
+                    let value = self.stack.pop(line)?;
+                    if self.handlers.is_empty() {
+                        return runtime_error(
+                            &format!("Uncaught exception: {}", self.display_value(&value)),
+                            line,
+                        );
+                    }
+                    self.unwind();
+                    self.stack.push(value);
+                }
+                Some(OpCode::Exit) => match self.stack.pop(line)? {
+                    Value::Number(n) => match exit_status(n) {
+                        Some(status) => return Err(InterpreterError::Exit(status)),
+                        None => return runtime_error(&bad_exit_status(n), line),
+                    },
+                    _ => return runtime_error("Expected number as exit status", line),
+                },
+                Some(OpCode::Interpolate) => {
+                    let nparts = self.read_byte() as usize;
+                    let mut parts = Vec::with_capacity(nparts);
+                    for _ in 0..nparts {
+                        parts.push(self.stack.pop(line)?);
+                    }
+                    let mut out = String::new();
+                    for p in parts.iter().rev() {
+                        self.format_value(p, &mut out, &mut Vec::new(), false);
+                    }
+                    self.stack.push(Value::String(out));
+                }
+                Some(OpCode::BadDestructure) => {
+                    let kind = self.read_byte();
+                    let operand = self.stack.pop(line)?;
+                    let value = self.stack.pop(line)?;
+                    return runtime_error(&self.destructure_error(kind, operand, &value), line);
+                }
+                Some(OpCode::NoMatch) => {
+                    let value = self.stack.pop(line)?;
+                    return runtime_error(
+                        &format!(
+                            "Value {} did not match any pattern",
+                            self.display_value(&value)
+                        ),
+                        line,
+                    );
+                }
+
                 None => return runtime_error("Bad instruction", line),
             }
+            let limit = self.options.limits.value_stack;
+            if self.stack.top > limit {
+                return self.stack_overflow(self.function, "value stack", limit, line);
+            }
         }
     }
 
@@ -340,62 +998,187 @@ impl VM {
         }
         Ok(())
     }
-    fn op_assign_local(&mut self, current_line: usize) -> Result<(), InterpreterError> {
-        let number = self.read_byte() as usize + self.locals_base;
+    fn op_assign_local(
+        &mut self,
+        number: usize,
+        current_line: usize,
+    ) -> Result<(), InterpreterError> {
+        let number = number + self.locals_base;
         if number >= self.locals_top {
             return runtime_error("Local store out of range", current_line);
         }
-        self.locals[number as usize] = self.stack.pop(current_line)?;
+        self.locals[number] = self.stack.pop(current_line)?;
         Ok(())
     }
-    fn op_load_local(&mut self, current_line: usize) -> Result<(), InterpreterError> {
-        let number = self.read_byte() as usize + self.locals_base;
+    fn op_load_local(
+        &mut self,
+        number: usize,
+        current_line: usize,
+    ) -> Result<(), InterpreterError> {
+        let number = number + self.locals_base;
         if number >= self.locals_top {
             return runtime_error("Local load out of range", current_line);
         }
-        let value = self.locals[number as usize].clone();
+        let value = self.locals[number].clone();
         self.stack.push(value);
         Ok(())
     }
 
     fn op_function_entry(&mut self, current_line: usize) -> Result<(), InterpreterError> {
-        let localsn = self.read_byte() as usize;
+        let entry = self.ip - 1;
+        self.function = entry;
+        let localsn = self.read_short();
+        let limits = self.options.limits;
+        if self.locals_base + localsn > limits.locals {
+            return self.stack_overflow(entry, "locals", limits.locals, current_line);
+        }
         self.locals_top = self.locals_base + localsn;
-        if self.locals_top >= STACK_SIZE {
-            return runtime_error("Stack overflow!", current_line);
+        if self.locals_top > self.locals.len() {
+            self.locals.resize(self.locals_top, Value::Nil);
         }
         Ok(())
     }
 
     fn op_call(&mut self, current_line: usize) -> Result<(), InterpreterError> {
-        let callee = match self.stack.pop(current_line)? {
-            Value::Callable(c) => c,
+        let nargs = self.read_byte();
+        let (callee, closure) = match self.stack.pop(current_line)? {
+            Value::Callable(c) => (c, None),
+            Value::NativeFunction(n) => return self.call_native(n, nargs, current_line),
+            Value::ReferenceId(id) => match &self.heap[id] {
+                ReferenceType::Closure(c) => (c.function, Some(id)),
+                _ => return runtime_error("Non-callable value called", current_line),
+            },
             _ => return runtime_error("Non-callable value called", current_line),
         };
+        self.call_function(callee, closure, nargs, current_line)
+    }
+
+    fn call_function(
+        &mut self,
+        callee: usize,
+        closure: Option<usize>,
+        nargs: u8,
+        current_line: usize,
+    ) -> Result<(), InterpreterError> {
+        let arity = self.chunk.function_arities[callee];
+        if arity != nargs {
+            return runtime_error(
+                &format!(
+                    "Function {} expects {} arguments but was called with {}.",
+                    self.chunk.function_name(callee),
+                    arity,
+                    nargs
+                ),
+                current_line,
+            );
+        }
 
-        self.return_stack[self.return_stack_top] = CallFrame {
+        let address = self.chunk.function_locations[callee];
+        let limit = self.options.limits.call_depth;
+        if self.return_stack_top >= limit {
+            return self.stack_overflow(address, "call depth", limit, current_line);
+        }
+        let frame = CallFrame {
             return_address: self.ip,
             locals_base: self.locals_base,
+            closure: self.closure,
+            function: self.function,
         };
+        if self.return_stack_top < self.return_stack.len() {
+            self.return_stack[self.return_stack_top] = frame;
+        } else {
+            self.return_stack.push(frame);
+        }
         self.return_stack_top += 1;
-        self.ip = callee;
+        self.ip = address;
         self.locals_base = self.locals_top;
+        self.closure = closure;
+        Ok(())
+    }
+
+    // entry is the address of the function being entered.
+    fn stack_overflow<T>(
+        &self,
+        entry: usize,
+        limit_name: &str,
+        limit: usize,
+        line: usize,
+    ) -> Result<T, InterpreterError> {
+        runtime_error(
+            &format!(
+                "Stack overflow in function {} at call depth {} ({} limit is {})",
+                self.function_name_at(entry),
+                self.return_stack_top,
+                limit_name,
+                limit
+            ),
+            line,
+        )
+    }
+
+    fn call_native(
+        &mut self,
+        n: usize,
+        nargs: u8,
+        current_line: usize,
+    ) -> Result<(), InterpreterError> {
+        let native = &self.natives[n];
+        if native.arity != nargs {
+            return runtime_error(
+                &format!(
+                    "Function {} expects {} arguments but was called with {}.",
+                    native.name, native.arity, nargs
+                ),
+                current_line,
+            );
+        }
+        let args_start = self.stack.top - nargs as usize;
+        let result = (native.function)(
+            &mut self.heap,
+            &self.stack.stack[args_start..self.stack.top],
+        );
+        self.stack.top = args_start;
+        let value = with_line(result, current_line)?;
+        self.stack.push(value);
+        // Natives allocate without checking the threshold, so check once
+        // their result is rooted on the stack.
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
         Ok(())
     }
 
-    fn op_jump_if_false(&mut self, current_line: usize) -> Result<(), InterpreterError> {
-        let target = self.read_signed_16();
+    fn op_jump_if_false(
+        &mut self,
+        current_line: usize,
+        wide: bool,
+    ) -> Result<(), InterpreterError> {
+        let target = self.read_jump_offset(wide);
         let value = self.stack.pop(current_line)?;
         if value.is_falsey() {
-            self.ip = (self.ip as isize + target as isize) as usize;
+            self.ip = (self.ip as isize + target) as usize;
         }
         Ok(())
     }
 
-    fn op_jump(&mut self, _current_line: usize) -> Result<(), InterpreterError> {
-        let target = self.read_signed_16();
-        self.ip = (self.ip as isize + target as isize) as usize;
-        Ok(())
+    fn op_push_handler(&mut self, wide: bool) {
+        let first_local = self.read_short() + self.locals_base;
+        let target = self.read_jump_offset(wide);
+        self.handlers.push(Handler {
+            catch_address: (self.ip as isize + target) as usize,
+            first_local,
+            stack_top: self.stack.top,
+            return_stack_top: self.return_stack_top,
+            locals_base: self.locals_base,
+            locals_top: self.locals_top,
+            closure: self.closure,
+            function: self.function,
+        });
+    }
+
+    fn op_jump(&mut self, wide: bool) {
+        let target = self.read_jump_offset(wide);
+        self.ip = (self.ip as isize + target) as usize;
     }
 
     fn op_index(&mut self, current_line: usize) -> Result<(), InterpreterError> {
@@ -415,6 +1198,11 @@ impl VM {
             }
 
             Value::ReferenceId(id) => {
+                if let ReferenceType::Instance(instance) = &self.heap[id] {
+                    let slot = self.instance_slot(instance.struct_id, &the_value, current_line)?;
+                    self.stack.push(instance.fields[slot].clone());
+                    return Ok(());
+                }
                 let ref_type = &mut self.heap[id];
                 match ref_type {
                     ReferenceType::Array(ref mut a) => {
@@ -447,6 +1235,32 @@ impl VM {
         Ok(())
     }
 
+    // Names no struct declares are compiled to indexing with a string, so
+    // look them up the same way as GetField and SetField.
+    fn instance_slot(
+        &self,
+        struct_id: usize,
+        key: &Value,
+        current_line: usize,
+    ) -> Result<usize, InterpreterError> {
+        let definition = &self.chunk.structs[struct_id];
+        match key {
+            Value::String(name) => match self
+                .chunk
+                .field_numbers
+                .get(name)
+                .and_then(|n| definition.slot(*n as usize))
+            {
+                Some(slot) => Ok(slot as usize),
+                None => runtime_error(
+                    &format!("Struct {} has no field {}", definition.name, name),
+                    current_line,
+                ),
+            },
+            _ => runtime_error("Struct fields must be indexed by name.", current_line),
+        }
+    }
+
     fn op_push_array(&mut self, current_line: usize) -> Result<(), InterpreterError> {
         let value = self.stack.pop(current_line)?;
         let array = self.stack.pop(current_line)?;
@@ -476,6 +1290,14 @@ impl VM {
         let indexer = self.stack.pop(current_line)?;
         match indexer {
             Value::ReferenceId(id) => {
+                if let ReferenceType::Instance(instance) = &self.heap[id] {
+                    let slot =
+                        self.instance_slot(instance.struct_id, &index_value, current_line)?;
+                    if let ReferenceType::Instance(instance) = &mut self.heap[id] {
+                        instance.fields[slot] = new_value;
+                    }
+                    return Ok(());
+                }
                 let ref_type = &mut self.heap[id];
                 match ref_type {
                     ReferenceType::Array(ref mut a) => {
@@ -507,6 +1329,7 @@ impl VM {
     }
 
     fn op_builtin_call(&mut self, current_line: usize) -> Result<(), InterpreterError> {
+        let nargs = self.read_byte();
         let builtin = self.stack.pop(current_line)?;
         let callee = self.stack.pop(current_line)?;
         let builtin = if let Value::String(s) = builtin {
@@ -515,8 +1338,29 @@ impl VM {
             return runtime_error("Expected builtin name", current_line);
         };
 
+        // User defined methods take priority over builtins. The receiver is
+        // passed as an extra last argument.
+        if let Some(method) = self.find_method(&callee, &builtin) {
+            // Report counts without the receiver, as written at the call site.
+            let arity = self.chunk.function_arities[method] - 1;
+            if arity != nargs {
+                return runtime_error(
+                    &format!(
+                        "Method {} expects {} arguments but was called with {}.",
+                        self.chunk.function_name(method),
+                        arity,
+                        nargs
+                    ),
+                    current_line,
+                );
+            }
+            self.stack.push(callee);
+            return self.call_function(method, None, nargs + 1, current_line);
+        }
+
         if builtin == "to_string" {
-            self.stack.push(Value::String(format!("{}", callee)));
+            let s = self.display_value(&callee);
+            self.stack.push(Value::String(s));
         } else {
             // TODO: Some kind of data driven solution rather than hardcoded ifs.
             match callee {
@@ -573,13 +1417,33 @@ impl VM {
                         }
                     }
                     ReferenceType::External(ref mut e) => {
-                        let arity = e.get_arity(&builtin);
-                        let mut args = Vec::new();
-                        for _ in 0..arity {
-                            args.push(self.stack.pop(current_line)?)
+                        if builtin == "methods" && !e.methods().contains(&"methods") {
+                            let methods = e
+                                .methods()
+                                .iter()
+                                .map(|m| Value::String(m.to_string()))
+                                .collect();
+                            let id = self.new_reference_type(ReferenceType::Array(methods));
+                            self.stack.push(Value::ReferenceId(id));
+                            return Ok(());
+                        }
+                        let arity = with_line(e.get_arity(&builtin), current_line)?;
+                        if arity != nargs as usize {
+                            return runtime_error(
+                                &format!(
+                                    "Method {} of {} expects {} arguments but was called with {}.",
+                                    builtin,
+                                    e.type_name(),
+                                    arity,
+                                    nargs
+                                ),
+                                current_line,
+                            );
                         }
-                        let result = e.call(&builtin, args);
-                        match result {
+                        let args_start = self.stack.top - arity;
+                        let args = self.stack.stack[args_start..self.stack.top].to_vec();
+                        self.stack.top = args_start;
+                        match with_line(e.call(&builtin, args), current_line)? {
                             ValueOrRef::Value(v) => {
                                 self.stack.push(v);
                             }
@@ -589,6 +1453,15 @@ impl VM {
                             }
                         }
                     }
+                    ReferenceType::Instance(i) => {
+                        return runtime_error(
+                            &format!(
+                                "Struct {} has no method {}",
+                                self.chunk.structs[i.struct_id].name, builtin
+                            ),
+                            current_line,
+                        )
+                    }
                     _ => return runtime_error("Unknown builtin", current_line),
                 },
 
@@ -613,9 +1486,13 @@ impl VM {
                     } else if builtin == "parseNumber" {
                         self.stack.push(Value::Number(s.parse().unwrap()));
                     } else if builtin == "regex" {
-                        let id = self.new_reference_type(ReferenceType::External(Box::new(
-                            regex::Regex::new(&s).unwrap(),
-                        )));
+                        let regex = match regex::Regex::new(&s) {
+                            Ok(r) => r,
+                            Err(e) => {
+                                return runtime_error(&format!("Bad regex: {}", e), current_line)
+                            }
+                        };
+                        let id = self.new_reference_type(ReferenceType::External(Box::new(regex)));
                         self.stack.push(Value::ReferenceId(id));
                     } else {
                         return runtime_error("Unknown string builtin", current_line);
@@ -638,6 +1515,16 @@ impl VM {
         Ok(())
     }
 
+    fn find_method(&self, value: &Value, name: &str) -> Option<usize> {
+        if let Value::ReferenceId(id) = value {
+            if let ReferenceType::Instance(i) = &self.heap[*id] {
+                let methods = &self.chunk.structs[i.struct_id].methods;
+                return methods.get(name).cloned();
+            }
+        }
+        None
+    }
+
     fn op_make_range(&mut self, current_line: usize) -> Result<(), InterpreterError> {
         let right = if let Value::Number(n) = self.stack.pop(current_line)? {
             n
@@ -653,15 +1540,15 @@ impl VM {
         Ok(())
     }
 
-    fn op_for_loop(&mut self, current_line: usize) -> Result<(), InterpreterError> {
-        let local_n = self.read_byte();
-        let jump_target = self.read_signed_16();
-        let target_ip = (self.ip as isize + jump_target as isize) as usize;
+    fn op_for_loop(&mut self, current_line: usize, wide: bool) -> Result<(), InterpreterError> {
+        let local_n = self.read_short();
+        let jump_target = self.read_jump_offset(wide);
+        let target_ip = (self.ip as isize + jump_target) as usize;
         let range = self.stack.pop(current_line)?;
         match range {
             Value::Range(l, r) => {
                 if l < r {
-                    self.locals[local_n as usize + self.locals_base] = Value::Number(l);
+                    self.locals[local_n + self.locals_base] = Value::Number(l);
                     self.stack.push(Value::Range(l + 1.0, r));
                 } else {
                     self.ip = target_ip;
@@ -670,7 +1557,7 @@ impl VM {
             Value::ReferenceId(id) => match &mut self.heap[id] {
                 ReferenceType::Array(a) => {
                     if !a.is_empty() {
-                        self.locals[local_n as usize + self.locals_base] = Value::Number(0.0);
+                        self.locals[local_n + self.locals_base] = Value::Number(0.0);
                         self.stack.push(Value::Range(1.0, a.len() as f64));
                     } else {
                         self.ip = target_ip;
@@ -680,7 +1567,7 @@ impl VM {
                     let keys: Vec<_> = m.keys().cloned().collect();
                     let len = keys.len();
                     if len > 0 {
-                        self.locals[local_n as usize + self.locals_base] = Value::from(&keys[0]);
+                        self.locals[local_n + self.locals_base] = Value::from(&keys[0]);
                         self.stack.push(Value::MapForContext(keys, 1.0, len as f64));
                     } else {
                         self.ip = target_ip;
@@ -690,11 +1577,10 @@ impl VM {
             },
             Value::MapForContext(keys, l, r) => {
                 if l < r {
-                    self.locals[local_n as usize + self.locals_base] =
-                        Value::from(&keys[l as usize]);
+                    self.locals[local_n + self.locals_base] = Value::from(&keys[l as usize]);
                     self.stack.push(Value::MapForContext(keys, l + 1.0, r));
                 } else {
-                    self.ip = (self.ip as isize + jump_target as isize) as usize;
+                    self.ip = (self.ip as isize + jump_target) as usize;
                 }
             }
             _ => return runtime_error("Don't know how to for over that", current_line),
@@ -720,11 +1606,11 @@ impl VM {
         Ok(())
     }
 
-    fn op_jump_if_true(&mut self, current_line: usize) -> Result<(), InterpreterError> {
-        let target = self.read_signed_16();
+    fn op_jump_if_true(&mut self, current_line: usize, wide: bool) -> Result<(), InterpreterError> {
+        let target = self.read_jump_offset(wide);
         let value = self.stack.pop(current_line)?;
         if value.is_truey() {
-            self.ip = (self.ip as isize + target as isize) as usize;
+            self.ip = (self.ip as isize + target) as usize;
         }
         Ok(())
     }
@@ -756,6 +1642,375 @@ impl VM {
         Ok(())
     }
 
+    fn op_new_enum(&mut self, current_line: usize) -> Result<(), InterpreterError> {
+        let enum_id = self.read_byte() as usize;
+        let variant = self.read_byte() as usize;
+        let arity = self.chunk.enums[enum_id].variants[variant].arity as usize;
+        let mut payload = Vec::with_capacity(arity);
+        for _ in 0..arity {
+            payload.push(self.stack.pop(current_line)?);
+        }
+        payload.reverse();
+        let id = self.new_reference_type(ReferenceType::Enum(EnumValue {
+            enum_id,
+            variant,
+            payload,
+        }));
+        self.stack.push(Value::ReferenceId(id));
+        Ok(())
+    }
+
+    // Restore the state saved by the innermost handler and continue at its
+    // catch block. The caller pushes the error value.
+    fn unwind(&mut self) {
+        let handler = self.handlers.pop().unwrap();
+        self.close_upvalues(handler.first_local);
+        self.stack.top = handler.stack_top;
+        self.return_stack_top = handler.return_stack_top;
+        self.locals_base = handler.locals_base;
+        self.locals_top = handler.locals_top;
+        self.closure = handler.closure;
+        self.function = handler.function;
+        self.ip = handler.catch_address;
+    }
+
+    pub fn display_value(&self, value: &Value) -> String {
+        let mut out = String::new();
+        self.format_value(value, &mut out, &mut Vec::new(), false);
+        out
+    }
+
+    // Writes a readable form of value, following references into the heap.
+    // seen holds the objects currently being formatted so cycles terminate,
+    // and strings are quoted when nested inside another value.
+    fn format_value(&self, value: &Value, out: &mut String, seen: &mut Vec<usize>, nested: bool) {
+        let id = match value {
+            Value::String(s) if nested => {
+                out.push_str(&format!("{:?}", s));
+                return;
+            }
+            Value::Callable(c) => {
+                out.push_str(&format!("<fn {}>", self.chunk.function_name(*c)));
+                return;
+            }
+            Value::NativeFunction(n) => {
+                out.push_str(&format!("<native fn {}>", self.natives[*n].name));
+                return;
+            }
+            Value::ReferenceId(id) => *id,
+            Value::MapForContext(..) => {
+                out.push_str("<map context>");
+                return;
+            }
+            _ => {
+                out.push_str(&format!("{}", value));
+                return;
+            }
+        };
+        if seen.contains(&id) {
+            out.push_str("...");
+            return;
+        }
+        seen.push(id);
+        match &self.heap[id] {
+            ReferenceType::Array(a) => {
+                out.push('[');
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.format_value(v, out, seen, true);
+                }
+                out.push(']');
+            }
+            ReferenceType::Map(m) => {
+                let mut keys: Vec<_> = m.keys().collect();
+                keys.sort();
+                out.push_str("#{");
+                for (i, k) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.format_value(&Value::from(k), out, seen, true);
+                    out.push_str(": ");
+                    self.format_value(&m[k], out, seen, true);
+                }
+                out.push('}');
+            }
+            ReferenceType::Instance(instance) => {
+                let definition = &self.chunk.structs[instance.struct_id];
+                out.push_str(&definition.name);
+                out.push_str(" {");
+                for (i, (name, v)) in definition.fields.iter().zip(&instance.fields).enumerate() {
+                    out.push_str(if i > 0 { ", " } else { " " });
+                    out.push_str(name);
+                    out.push_str(": ");
+                    self.format_value(v, out, seen, true);
+                }
+                out.push_str(" }");
+            }
+            ReferenceType::Enum(e) => {
+                let definition = &self.chunk.enums[e.enum_id];
+                out.push_str(&definition.name);
+                out.push_str("::");
+                out.push_str(&definition.variants[e.variant].name);
+                if !e.payload.is_empty() {
+                    out.push('(');
+                    for (i, v) in e.payload.iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        self.format_value(v, out, seen, true);
+                    }
+                    out.push(')');
+                }
+            }
+            ReferenceType::Closure(c) => {
+                out.push_str(&format!("<fn {}>", self.chunk.function_name(c.function)));
+            }
+            ReferenceType::External(e) => {
+                out.push('<');
+                out.push_str(e.type_name());
+                out.push('>');
+            }
+            ReferenceType::Upvalue(_) => out.push_str("<upvalue>"),
+        }
+        seen.pop();
+    }
+
+    // Look up the key named by the next constant in a map or struct instance.
+    fn lookup_key(&mut self, value: &Value, key: Value) -> Option<Value> {
+        let key = match key {
+            Value::String(s) => s,
+            _ => return None,
+        };
+        match value {
+            Value::ReferenceId(id) => match &self.heap[*id] {
+                ReferenceType::Map(m) => m.get(&HashableValue::String(key)).cloned(),
+                ReferenceType::Instance(instance) => {
+                    let definition = &self.chunk.structs[instance.struct_id];
+                    let field = *self.chunk.field_numbers.get(&key)?;
+                    let slot = definition.slot(field as usize)?;
+                    Some(instance.fields[slot as usize].clone())
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn destructure_error(&self, kind: u8, operand: Value, value: &Value) -> String {
+        let (array_length, is_map) = match value {
+            Value::ReferenceId(id) => match &self.heap[*id] {
+                ReferenceType::Array(a) => (Some(a.len()), false),
+                ReferenceType::Map(_) => (None, true),
+                _ => (None, false),
+            },
+            _ => (None, false),
+        };
+        let got = match array_length {
+            Some(n) => format!("{} elements", n),
+            None if *value == Value::Nil => "nil".to_string(),
+            None => format!(
+                "{} {}",
+                convert::type_name(value, &self.heap),
+                self.display_value(value)
+            ),
+        };
+        match (kind, operand) {
+            (chunk::EXPECTED_ARRAY, n) => {
+                format!("Expected array of {} elements, got {}", n, got)
+            }
+            (chunk::EXPECTED_ARRAY_AT_LEAST, n) => {
+                format!("Expected array of at least {} elements, got {}", n, got)
+            }
+            (chunk::EXPECTED_KEY, key) if is_map => format!("Map has no key '{}'", key),
+            (chunk::EXPECTED_KEY, key) => format!(
+                "Expected map with key '{}', got {}",
+                key,
+                self.display_value(value)
+            ),
+            (_, expected) => format!("Expected {}, got {}", expected, self.display_value(value)),
+        }
+    }
+
+    // Enum values compare by variant and payload, everything else by value
+    // (so by identity for other reference types).
+    fn values_equal(&self, a: &Value, b: &Value) -> bool {
+        if let (Value::ReferenceId(x), Value::ReferenceId(y)) = (a, b) {
+            if let (ReferenceType::Enum(e1), ReferenceType::Enum(e2)) =
+                (&self.heap[*x], &self.heap[*y])
+            {
+                return e1.enum_id == e2.enum_id
+                    && e1.variant == e2.variant
+                    && e1
+                        .payload
+                        .iter()
+                        .zip(&e2.payload)
+                        .all(|(v1, v2)| self.values_equal(v1, v2));
+            }
+        }
+        a == b
+    }
+
+    fn op_init_field(&mut self, current_line: usize) -> Result<(), InterpreterError> {
+        let slot = self.read_byte() as usize;
+        let value = self.stack.pop(current_line)?;
+        if let Value::ReferenceId(id) = self.stack.peek() {
+            if let ReferenceType::Instance(instance) = &mut self.heap[id] {
+                instance.fields[slot] = value;
+                return Ok(());
+            }
+        }
+        runtime_error("Field init on non-struct", current_line)
+    }
+
+    fn op_get_field(&mut self, current_line: usize) -> Result<(), InterpreterError> {
+        let field = self.read_byte() as usize;
+        let object = self.stack.pop(current_line)?;
+        let value = match object {
+            Value::ReferenceId(id) => match &self.heap[id] {
+                ReferenceType::Instance(instance) => {
+                    let definition = &self.chunk.structs[instance.struct_id];
+                    match definition.slot(field) {
+                        Some(slot) => instance.fields[slot as usize].clone(),
+                        None => {
+                            return runtime_error(
+                                &format!(
+                                    "Struct {} has no field {}",
+                                    definition.name, self.chunk.field_names[field]
+                                ),
+                                current_line,
+                            )
+                        }
+                    }
+                }
+                ReferenceType::Map(m) => {
+                    let key = HashableValue::String(self.chunk.field_names[field].clone());
+                    m.get(&key).unwrap_or(&Value::Nil).clone()
+                }
+                _ => return runtime_error("Don't know how to get field of that.", current_line),
+            },
+            _ => return runtime_error("Don't know how to get field of that.", current_line),
+        };
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn op_set_field(&mut self, current_line: usize) -> Result<(), InterpreterError> {
+        let field = self.read_byte() as usize;
+        let value = self.stack.pop(current_line)?;
+        let object = self.stack.pop(current_line)?;
+        match object {
+            Value::ReferenceId(id) => match &mut self.heap[id] {
+                ReferenceType::Instance(instance) => {
+                    let definition = &self.chunk.structs[instance.struct_id];
+                    match definition.slot(field) {
+                        Some(slot) => instance.fields[slot as usize] = value,
+                        None => {
+                            return runtime_error(
+                                &format!(
+                                    "Struct {} has no field {}",
+                                    definition.name, self.chunk.field_names[field]
+                                ),
+                                current_line,
+                            )
+                        }
+                    }
+                }
+                ReferenceType::Map(m) => {
+                    let key = HashableValue::String(self.chunk.field_names[field].clone());
+                    m.insert(key, value);
+                }
+                _ => return runtime_error("Don't know how to set field of that.", current_line),
+            },
+            _ => return runtime_error("Don't know how to set field of that.", current_line),
+        }
+        Ok(())
+    }
+
+    fn op_closure(&mut self, current_line: usize) -> Result<(), InterpreterError> {
+        let function = match self.stack.pop(current_line)? {
+            Value::Callable(c) => c,
+            _ => return runtime_error("Expected function for closure.", current_line),
+        };
+        let count = self.read_byte();
+        let mut upvalues = Vec::new();
+        for _ in 0..count {
+            let is_local = self.read_byte() == 1;
+            let index = self.read_short();
+            if is_local {
+                upvalues.push(self.capture_upvalue(self.locals_base + index));
+            } else {
+                upvalues.push(self.current_upvalue(index, current_line)?);
+            }
+        }
+        let id = self.new_reference_type(ReferenceType::Closure(Closure { function, upvalues }));
+        self.stack.push(Value::ReferenceId(id));
+        Ok(())
+    }
+
+    fn op_load_upvalue(&mut self, current_line: usize) -> Result<(), InterpreterError> {
+        let index = self.read_byte() as usize;
+        let value = match &self.heap[self.current_upvalue(index, current_line)?] {
+            ReferenceType::Upvalue(Upvalue::Open(slot)) => self.locals[*slot].clone(),
+            ReferenceType::Upvalue(Upvalue::Closed(value)) => value.clone(),
+            _ => return runtime_error("Expected upvalue.", current_line),
+        };
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn op_assign_upvalue(&mut self, current_line: usize) -> Result<(), InterpreterError> {
+        let index = self.read_byte() as usize;
+        let value = self.stack.pop(current_line)?;
+        let id = self.current_upvalue(index, current_line)?;
+        match &mut self.heap[id] {
+            ReferenceType::Upvalue(Upvalue::Open(slot)) => self.locals[*slot] = value,
+            ReferenceType::Upvalue(Upvalue::Closed(v)) => *v = value,
+            _ => return runtime_error("Expected upvalue.", current_line),
+        }
+        Ok(())
+    }
+
+    fn current_upvalue(
+        &self,
+        index: usize,
+        current_line: usize,
+    ) -> Result<usize, InterpreterError> {
+        match self.closure.map(|id| &self.heap[id]) {
+            Some(ReferenceType::Closure(c)) => Ok(c.upvalues[index]),
+            _ => runtime_error("Upvalue access outside of a closure.", current_line),
+        }
+    }
+
+    // Find the open upvalue for a locals slot, creating it if it doesn't
+    // exist yet so that every closure capturing a variable shares it.
+    fn capture_upvalue(&mut self, slot: usize) -> usize {
+        if let Some((_, id)) = self.open_upvalues.iter().find(|(s, _)| *s == slot) {
+            return *id;
+        }
+        let id = self.new_reference_type(ReferenceType::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push((slot, id));
+        id
+    }
+
+    // Close every open upvalue pointing at a locals slot at or above first_slot,
+    // moving the current value of the local into the upvalue.
+    fn close_upvalues(&mut self, first_slot: usize) {
+        let mut i = 0;
+        while i < self.open_upvalues.len() {
+            let (slot, id) = self.open_upvalues[i];
+            if slot >= first_slot {
+                let value = self.locals[slot].clone();
+                self.heap[id] = ReferenceType::Upvalue(Upvalue::Closed(value));
+                self.open_upvalues.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
     fn read_byte(&mut self) -> u8 {
         self.ip += 1;
         self.chunk.code[self.ip - 1]
@@ -771,13 +2026,40 @@ impl VM {
         (number | number2 << 8) as i16
     }
 
+    fn read_jump_offset(&mut self, wide: bool) -> isize {
+        if wide {
+            // Sign extend from 24 bits.
+            ((self.read_long() as u32) << 8) as i32 as isize >> 8
+        } else {
+            self.read_signed_16() as isize
+        }
+    }
+
+    fn read_short(&mut self) -> usize {
+        let number = self.read_byte() as usize;
+        let number2 = self.read_byte() as usize;
+        number | number2 << 8
+    }
+
+    fn read_long(&mut self) -> usize {
+        let number = self.read_byte() as usize;
+        let number2 = self.read_byte() as usize;
+        let number3 = self.read_byte() as usize;
+        number | number2 << 8 | number3 << 16
+    }
+
     fn read_constant(&mut self) -> Value {
         let constant_number = self.read_byte();
         self.chunk.constants[constant_number as usize].clone()
     }
 
     fn new_reference_type(&mut self, value: ReferenceType) -> usize {
-        self.heap.push(value);
-        self.heap.len() - 1
+        let id = self.heap.allocate(value);
+        if self.heap.should_collect() {
+            // The new object isn't on the stack yet, so root it explicitly.
+            self.heap.mark_object(id);
+            self.collect_garbage();
+        }
+        id
     }
 }
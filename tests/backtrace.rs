@@ -0,0 +1,156 @@
+mod common;
+
+use common::run_script;
+use nail::vm::{InterpreterError, VM};
+
+// The backtrace of a runtime error as (function, line) pairs, outermost first.
+fn trace(source: &str) -> Vec<(String, usize)> {
+    let mut vm = VM::new();
+    match vm.interpret(source) {
+        Err(InterpreterError::RuntimeError(_, _, trace)) => trace
+            .into_iter()
+            .map(|frame| (frame.function, frame.line))
+            .collect(),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
+
+fn frames(expected: &[(&str, usize)]) -> Vec<(String, usize)> {
+    expected
+        .iter()
+        .map(|(function, line)| (function.to_string(), *line))
+        .collect()
+}
+
+const NESTED: &str = "
+fn inner(x) {
+    x + nil
+}
+
+fn outer(x) {
+    let y = x * 2;
+    inner(y)
+}
+
+fn main() {
+    outer(1)
+}
+";
+
+#[test]
+fn each_call_is_listed_with_its_line() {
+    assert_eq!(
+        trace(NESTED),
+        frames(&[("main", 12), ("outer", 8), ("inner", 3)])
+    );
+}
+
+#[test]
+fn backtraces_are_printed_most_recent_call_last() {
+    let mut vm = VM::new();
+    let error = vm.interpret(NESTED).unwrap_err().to_string();
+    let lines: Vec<_> = error.lines().collect();
+    assert_eq!(
+        &lines[1..],
+        &[
+            "Traceback (most recent call last):",
+            "  in main, line 12",
+            "  in outer, line 8",
+            "  in inner, line 3",
+        ]
+    );
+}
+
+#[test]
+fn closures_and_methods_are_named() {
+    let source = "
+struct Counter { n }
+
+impl Counter {
+    fn check(self) {
+        let f = fn() { self.n:missing() };
+        f()
+    }
+}
+
+fn main() {
+    Counter { n: 1 }:check()
+}
+";
+    let trace = trace(source);
+    assert_eq!(trace.len(), 3);
+    assert_eq!(trace[0], ("main".to_string(), 12));
+    assert_eq!(trace[1], ("Counter::check".to_string(), 7));
+    assert_eq!(trace[2].1, 6);
+}
+
+#[test]
+fn repeated_frames_are_collapsed() {
+    let source = "
+fn down(n) {
+    if n == 0 {
+        nil + 1
+    } else {
+        down(n - 1)
+    }
+}
+
+fn main() {
+    down(50)
+}
+";
+    let mut vm = VM::new();
+    let error = vm.interpret(source).unwrap_err();
+    let message = error.to_string();
+    assert_eq!(
+        message,
+        "Runtime Error, line 4: Bad or mismatched arguments to +
+Traceback (most recent call last):
+  in main, line 11
+  in down, line 6
+  ... repeated 49 more times
+  in down, line 4"
+    );
+}
+
+#[test]
+fn caught_errors_do_not_leave_frames_behind() {
+    let source = "
+fn fail() {
+    nil + 1
+}
+
+fn main() {
+    try { fail() } catch e { nil };
+    let m = #{};
+    m:missing()
+}
+";
+    assert_eq!(trace(source), frames(&[("main", 9)]));
+}
+
+#[test]
+fn host_calls_have_a_backtrace() {
+    let mut vm = VM::new();
+    vm.load(NESTED).unwrap();
+    match vm.call("outer", &[nail::Value::Number(1.0)]) {
+        Err(InterpreterError::RuntimeError(_, line, trace)) => {
+            assert_eq!(line, 3);
+            let trace: Vec<_> = trace.into_iter().map(|f| (f.function, f.line)).collect();
+            assert_eq!(trace, frames(&[("outer", 8), ("inner", 3)]));
+        }
+        other => panic!("expected a runtime error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn nailc_prints_the_backtrace() {
+    let run = run_script(NESTED, &[]);
+    assert_eq!(run.status, 70);
+    assert!(
+        run.stderr
+            .contains("Traceback (most recent call last):\n  in main, line 12\n  in outer, line 8"),
+        "{}",
+        run.stderr
+    );
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use common::{nailc, run_script, temp_dir, write_file, Run};
+
+const HELLO: &str = "fn main() {\n    print \"hello\";\n}\n";
+
+#[test]
+fn scripts_print_only_their_own_output_by_default() {
+    let run = run_script(HELLO, &[]);
+    assert_eq!(run.status, 0);
+    assert_eq!(run.stdout, "hello\n");
+    assert_eq!(run.stderr, "");
+}
+
+// Run source with nailc options before the script's path.
+fn run_with(options: &[&str], source: &str) -> Run {
+    let path = write_file(&temp_dir(), "main.nlx", source);
+    let mut args = options.to_vec();
+    args.push(path.to_str().unwrap());
+    nailc(&args, "")
+}
+
+#[test]
+fn diagnostics_are_printed_when_asked_for() {
+    let run = run_with(&["--dump-bytecode"], HELLO);
+    assert!(run.stdout.contains("OP_PRINT"), "{}", run.stdout);
+
+    let run = run_with(&["--dump-ast"], HELLO);
+    assert!(run.stdout.contains("PrintStatement"), "{}", run.stdout);
+
+    let run = run_with(&["--dump-tokens"], HELLO);
+    assert!(run.stdout.contains("Identifier 'main'"), "{}", run.stdout);
+
+    let run = run_with(&["--timing"], HELLO);
+    assert!(run.stdout.starts_with("hello\n"), "{}", run.stdout);
+    assert!(run.stderr.contains("Compiled:"), "{}", run.stderr);
+}
+
+#[test]
+fn check_reports_errors_with_a_failing_exit_code() {
+    let dir = temp_dir();
+    let ok = write_file(&dir, "ok.nlx", HELLO);
+    let run = nailc(&["check", ok.to_str().unwrap()], "");
+    assert_eq!((run.status, run.stdout.as_str()), (0, ""));
+
+    let bad = write_file(&dir, "bad.nlx", "fn main() { nope }\n");
+    let run = nailc(&["check", bad.to_str().unwrap()], "");
+    assert_eq!(run.status, 65);
+    assert!(
+        run.stderr.contains("Undefined variable: nope"),
+        "{}",
+        run.stderr
+    );
+
+    let unparsable = write_file(&dir, "unparsable.nlx", "fn main( {\n");
+    let run = nailc(&["check", unparsable.to_str().unwrap()], "");
+    assert_eq!(run.status, 65);
+}
+
+#[test]
+fn inspection_subcommands_print_each_stage() {
+    let path = write_file(&temp_dir(), "main.nlx", HELLO);
+    let path = path.to_str().unwrap();
+
+    let run = nailc(&["disasm", path], "");
+    assert_eq!(run.status, 0);
+    assert!(run.stdout.contains("OP_PRINT"), "{}", run.stdout);
+
+    let run = nailc(&["tokens", path], "");
+    assert_eq!(run.status, 0);
+    assert!(run.stdout.contains("Print 'print'"), "{}", run.stdout);
+
+    let run = nailc(&["ast", path], "");
+    assert_eq!(run.status, 0);
+    assert!(run.stdout.contains("PrintStatement"), "{}", run.stdout);
+
+    let run = nailc(&["run", path], "");
+    assert_eq!((run.status, run.stdout.as_str()), (0, "hello\n"));
+}
+
+#[test]
+fn usage_and_io_errors_have_their_own_exit_codes() {
+    let run = nailc(&["check"], "");
+    assert_eq!(run.status, 64);
+    assert!(run.stderr.starts_with("Usage: nailc"), "{}", run.stderr);
+
+    let run = nailc(&["--bogus", "main.nlx"], "");
+    assert_eq!(run.status, 64);
+
+    let missing = temp_dir().join("missing.nlx");
+    let run = nailc(&["check", missing.to_str().unwrap()], "");
+    assert_eq!(run.status, 74);
+}
+
+#[test]
+fn main_receives_the_remaining_arguments() {
+    let source = "fn main(args) {\n    print args;\n}\n";
+    let run = run_script(source, &["a", "--b", "3"]);
+    assert_eq!(run.status, 0);
+    assert_eq!(run.stdout, "[\"a\", \"--b\", \"3\"]\n");
+
+    let run = run_script(source, &[]);
+    assert_eq!(run.stdout, "[]\n");
+}
+
+#[test]
+fn main_returning_a_number_sets_the_exit_code() {
+    assert_eq!(run_script("fn main() { 3 }", &[]).status, 3);
+    assert_eq!(run_script("fn main() { \"not a number\" }", &[]).status, 0);
+}
+
+#[test]
+fn exit_ends_the_script_even_inside_try() {
+    let source = "
+fn main() {
+    try { exit(5); } catch e { print \"caught\"; };
+    print \"after\";
+}
+";
+    let run = run_script(source, &[]);
+    assert_eq!((run.status, run.stdout.as_str()), (5, ""));
+}
+
+#[test]
+fn exit_statuses_must_fit_in_a_byte() {
+    assert_eq!(run_script("fn main() { exit(255); }", &[]).status, 255);
+    for status in ["300", "-1", "1.5"] {
+        let run = run_script(&format!("fn main() {{\n    exit({});\n}}", status), &[]);
+        assert_eq!(run.status, 70);
+        assert!(
+            run.stderr.starts_with(&format!(
+                "Runtime Error, line 2: Exit status must be an integer from 0 to 255, got {}",
+                status
+            )),
+            "{}",
+            run.stderr
+        );
+    }
+
+    let run = run_script("fn main() { 256 }", &[]);
+    assert_eq!(run.status, 70);
+    assert_eq!(
+        run.stderr,
+        "Runtime Error: Exit status must be an integer from 0 to 255, got 256 (returned from main)\n"
+    );
+}
+
+#[test]
+fn errors_set_failing_exit_codes() {
+    let run = run_script("fn main() { nope }", &[]);
+    assert_eq!(run.status, 65);
+    let run = run_script("fn main() { nil + 1 }", &[]);
+    assert_eq!(run.status, 70);
+    assert!(
+        run.stderr.starts_with("Runtime Error, line 1"),
+        "{}",
+        run.stderr
+    );
+}
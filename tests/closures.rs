@@ -0,0 +1,168 @@
+mod common;
+
+use common::eval;
+
+#[test]
+fn closures_capture_enclosing_locals() {
+    let source = "
+fn adder(n) {
+    fn(x) { x + n }
+}
+
+fn main() {
+    let add2 = adder(2);
+    let add5 = adder(5);
+    [add2(1), add5(1)]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[3, 6]");
+}
+
+#[test]
+fn captured_locals_are_shared_and_mutable() {
+    let source = "
+fn main() {
+    let count = 0;
+    let inc = fn() { count += 1; };
+    let get = fn() { count };
+    inc();
+    inc();
+    count += 10;
+    [get(), count]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[12, 12]");
+}
+
+#[test]
+fn locals_are_closed_when_their_block_exits() {
+    let source = "
+fn main() {
+    let f = nil;
+    {
+        let x = 1;
+        f = fn() { x };
+    };
+    let y = 2;
+    let z = 3;
+    f()
+}
+";
+    assert_eq!(eval(source).unwrap(), "1");
+}
+
+#[test]
+fn each_loop_iteration_gets_its_own_locals() {
+    let source = "
+fn main() {
+    let fs = [];
+    let i = 0;
+    while i < 3 {
+        let j = i * 10;
+        fs:push(fn() { j });
+        i += 1;
+    };
+    [fs[0](), fs[1](), fs[2]()]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[0, 10, 20]");
+}
+
+#[test]
+fn continue_closes_captured_loop_locals() {
+    let source = "
+fn main() {
+    let fs = [];
+    let i = 0;
+    while i < 3 {
+        let j = i * 10;
+        fs:push(fn() { j });
+        i += 1;
+        continue;
+    };
+    [fs[0](), fs[1](), fs[2]()]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[0, 10, 20]");
+}
+
+#[test]
+fn break_closes_captured_loop_locals() {
+    let source = "
+fn main() {
+    let f = nil;
+    loop {
+        let captured = 42;
+        f = fn() { captured };
+        break;
+    };
+    let other = 7;
+    f()
+}
+";
+    assert_eq!(eval(source).unwrap(), "42");
+}
+
+#[test]
+fn break_closes_locals_of_nested_blocks() {
+    let source = "
+fn main() {
+    let f = nil;
+    loop {
+        let a = 1;
+        {
+            let b = 2;
+            f = fn() { a + b };
+            break;
+        };
+    };
+    let x = 100;
+    let y = 200;
+    f()
+}
+";
+    assert_eq!(eval(source).unwrap(), "3");
+}
+
+#[test]
+fn each_for_iteration_binds_a_fresh_loop_variable() {
+    let source = "
+fn main() {
+    let fs = [];
+    for i in 0..3 {
+        fs:push(fn() { i });
+    };
+    [fs[0](), fs[1](), fs[2]()]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[0, 1, 2]");
+}
+
+#[test]
+fn each_for_iteration_binds_fresh_pattern_variables() {
+    let source = "
+fn main() {
+    let fs = [];
+    for [a, b] in [[1, 2], [3, 4]] {
+        fs:push(fn() { a + b });
+    };
+    [fs[0](), fs[1]()]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[3, 7]");
+}
+
+#[test]
+fn continue_in_for_closes_the_loop_variable() {
+    let source = "
+fn main() {
+    let fs = [];
+    for i in 0..3 {
+        fs:push(fn() { i });
+        continue;
+    };
+    [fs[0](), fs[1](), fs[2]()]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[0, 1, 2]");
+}
@@ -0,0 +1,74 @@
+// Helpers shared by the integration tests. Not every test uses all of them.
+#![allow(dead_code)]
+
+use nail::vm::VM;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Run a program's main, returning its displayed result or the error message.
+pub fn eval(source: &str) -> Result<String, String> {
+    let mut vm = VM::new();
+    match vm.interpret(source) {
+        Ok(value) => Ok(vm.display_value(&value)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// A new empty directory for a test's files.
+pub fn temp_dir() -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "nail-test-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn write_file(dir: &Path, name: &str, source: &str) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+pub struct Run {
+    pub status: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// Run nailc with args, feeding it input on stdin.
+pub fn nailc(args: &[&str], input: &str) -> Run {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nailc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    Run {
+        status: output.status.code().unwrap_or(-1),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+// Run source as a script file with nailc, passing it args.
+pub fn run_script(source: &str, args: &[&str]) -> Run {
+    let dir = temp_dir();
+    let path = write_file(&dir, "main.nlx", source);
+    let mut all_args = vec![path.to_str().unwrap()];
+    all_args.extend(args);
+    nailc(&all_args, "")
+}
@@ -0,0 +1,74 @@
+use nail::vm::VM;
+use nail::Value;
+use std::collections::HashMap;
+
+#[test]
+fn integers_round_trip_at_their_limits() {
+    let mut vm = VM::new();
+    for n in [i32::MIN, -1, 0, i32::MAX] {
+        let value = vm.to_value(n).unwrap();
+        assert_eq!(vm.from_value::<i32>(&value).unwrap(), n);
+    }
+    assert_eq!(vm.from_value::<u8>(&Value::Number(255.0)).unwrap(), 255);
+    assert_eq!(vm.from_value::<i8>(&Value::Number(-128.0)).unwrap(), -128);
+    assert_eq!(
+        vm.from_value::<i64>(&Value::Number(-(2f64.powi(63))))
+            .unwrap(),
+        i64::MIN
+    );
+}
+
+#[test]
+fn out_of_range_or_fractional_numbers_do_not_convert() {
+    let vm = VM::new();
+    assert!(vm.from_value::<u8>(&Value::Number(256.0)).is_err());
+    assert!(vm.from_value::<i8>(&Value::Number(128.0)).is_err());
+    assert!(vm.from_value::<u32>(&Value::Number(-1.0)).is_err());
+    assert!(vm.from_value::<i32>(&Value::Number(1.5)).is_err());
+    assert!(vm.from_value::<i32>(&Value::Number(f64::NAN)).is_err());
+}
+
+#[test]
+fn sixty_four_bit_limits_are_exclusive() {
+    let vm = VM::new();
+    let two_64 = Value::Number(2f64.powi(64));
+    let two_63 = Value::Number(2f64.powi(63));
+    assert!(vm.from_value::<u64>(&two_64).is_err());
+    assert!(vm.from_value::<usize>(&two_64).is_err());
+    assert!(vm.from_value::<i64>(&two_63).is_err());
+    assert!(vm.from_value::<isize>(&two_63).is_err());
+    assert_eq!(vm.from_value::<u64>(&two_63).unwrap(), 1 << 63);
+}
+
+#[test]
+fn collections_convert_both_ways() {
+    let mut vm = VM::new();
+    let nested = vec![vec![1, 2], vec![], vec![3]];
+    let value = vm.to_value(nested.clone()).unwrap();
+    assert_eq!(vm.display_value(&value), "[[1, 2], [], [3]]");
+    assert_eq!(vm.from_value::<Vec<Vec<i32>>>(&value).unwrap(), nested);
+
+    let mut map = HashMap::new();
+    map.insert("a".to_string(), 1.5);
+    let value = vm.to_value(map.clone()).unwrap();
+    assert_eq!(vm.from_value::<HashMap<String, f64>>(&value).unwrap(), map);
+
+    let value = vm.to_value("text").unwrap();
+    assert_eq!(vm.from_value::<String>(&value).unwrap(), "text");
+    assert!(vm.from_value::<bool>(&Value::Boolean(true)).unwrap());
+}
+
+#[test]
+fn mismatched_types_name_what_was_expected() {
+    let vm = VM::new();
+    let e = vm
+        .from_value::<String>(&Value::Nil)
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("Expected"), "{}", e);
+    let e = vm
+        .from_value::<u8>(&Value::Number(300.0))
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("Expected u8 but got 300"), "{}", e);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::eval;
+
+#[test]
+fn let_destructures_arrays_and_maps() {
+    let source = "
+fn main() {
+    let [first, second, ..rest] = [1, 2, 3, 4];
+    let #{x, y: [low, high]} = #{x: 5, y: [6, 7]};
+    [first, second, rest, x, low, high]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[1, 2, [3, 4], 5, 6, 7]");
+}
+
+#[test]
+fn for_destructures_each_value() {
+    let source = "
+fn main() {
+    let total = 0;
+    for i, #{x, y: [low, high]} in [#{x: 1, y: [2, 3]}, #{x: 10, y: [20, 30]}] {
+        total += i + x + low + high;
+    };
+    total
+}
+";
+    assert_eq!(eval(source).unwrap(), "67");
+}
+
+fn destructure_error(source: &str) -> String {
+    let error = eval(source).unwrap_err();
+    error.lines().next().unwrap().to_string()
+}
+
+#[test]
+fn wrong_array_length_reports_the_expected_and_actual_lengths() {
+    assert_eq!(
+        destructure_error("fn main() {\n    let [a, b] = [1, 2, 3];\n}"),
+        "Runtime Error, line 2: Expected array of 2 elements, got 3 elements"
+    );
+    assert_eq!(
+        destructure_error("fn main() {\n    let [a, b, ..rest] = [1];\n}"),
+        "Runtime Error, line 2: Expected array of at least 2 elements, got 1 elements"
+    );
+    assert_eq!(
+        destructure_error("fn main() {\n    let [a, b] = 5;\n}"),
+        "Runtime Error, line 2: Expected array of 2 elements, got number 5"
+    );
+    assert_eq!(
+        destructure_error("fn main() {\n    let [a, ..b] = nil;\n}"),
+        "Runtime Error, line 2: Expected array of at least 1 elements, got nil"
+    );
+}
+
+#[test]
+fn missing_map_keys_are_named() {
+    assert_eq!(
+        destructure_error("fn main() {\n    let #{a, b} = #{a: 1};\n}"),
+        "Runtime Error, line 2: Map has no key 'b'"
+    );
+    assert_eq!(
+        destructure_error("fn main() {\n    let #{a} = [1];\n}"),
+        "Runtime Error, line 2: Expected map with key 'a', got [1]"
+    );
+}
+
+#[test]
+fn nested_mismatches_report_the_inner_value() {
+    assert_eq!(
+        destructure_error(
+            "fn main() {\n    for [name, [x, y]] in [[\"a\", [1, 2]], [\"b\", [1]]] {};\n}"
+        ),
+        "Runtime Error, line 2: Expected array of 2 elements, got 1 elements"
+    );
+}
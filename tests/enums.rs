@@ -0,0 +1,93 @@
+mod common;
+
+use common::eval;
+
+const SHAPES: &str = "
+enum Shape { Circle(r), Rect(w, h), Empty }
+
+fn area(shape) {
+    match shape {
+        Shape::Circle(r) => 3 * r * r,
+        Shape::Rect(w, h) => w * h,
+        Shape::Empty => 0,
+    }
+}
+";
+
+#[test]
+fn match_selects_the_variant_and_binds_its_values() {
+    let source = format!(
+        "{}
+fn main() {{
+    [area(Shape::Circle(2)), area(Shape::Rect(3, 4)), area(Shape::Empty)]
+}}",
+        SHAPES
+    );
+    assert_eq!(eval(&source).unwrap(), "[12, 12, 0]");
+}
+
+#[test]
+fn enum_values_compare_by_variant_and_payload() {
+    let source = "
+enum E { A(x), B }
+
+fn main() {
+    [E::A(1) == E::A(1), E::A(1) == E::A(2), E::B == E::B, E::A(1) == E::B]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[true, false, true, false]");
+}
+
+#[test]
+fn match_tries_literal_range_and_array_patterns_in_order() {
+    let source = "
+fn describe(x) {
+    match x {
+        0 => \"zero\",
+        1..10 => \"small\",
+        [] => \"empty\",
+        [first, _] => \"pair of {first}\",
+        _ => \"other\",
+    }
+}
+
+fn main() {
+    [describe(0), describe(5), describe([]), describe([7, 8]), describe(\"x\")]
+}
+";
+    assert_eq!(
+        eval(source).unwrap(),
+        "[\"zero\", \"small\", \"empty\", \"pair of 7\", \"other\"]"
+    );
+}
+
+#[test]
+fn unmatched_values_are_displayed_in_the_error() {
+    let source = "
+fn main() {
+    match [1, 2, 3] {
+        [] => 0,
+    }
+}
+";
+    assert!(eval(source)
+        .unwrap_err()
+        .starts_with("Runtime Error, line 3: Value [1, 2, 3] did not match any pattern"));
+}
+
+#[test]
+fn constructing_a_variant_with_the_wrong_number_of_values_is_a_compile_error() {
+    let source = format!("{}\nfn main() {{ Shape::Rect(1) }}", SHAPES);
+    assert!(eval(&source)
+        .unwrap_err()
+        .starts_with("Compiler error: Variant Shape::Rect has 2 values but 1 were given"));
+}
+
+#[test]
+fn too_many_enums_is_a_compile_error() {
+    let enums: Vec<_> = (0..257).map(|i| format!("enum E{} {{ A }}", i)).collect();
+    let source = format!("{}\nfn main() {{}}", enums.join("\n"));
+    assert!(eval(&source)
+        .unwrap_err()
+        .starts_with("Compiler error: Too many enums"));
+}
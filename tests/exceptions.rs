@@ -0,0 +1,98 @@
+mod common;
+
+use common::eval;
+
+#[test]
+fn thrown_values_are_caught() {
+    let source = "
+fn check(x) {
+    if x < 0 {
+        throw #{code: x};
+    };
+    x
+}
+
+fn main() {
+    [try { check(1) } catch e { nil }, try { check(-2) } catch e { e.code }]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[1, -2]");
+}
+
+#[test]
+fn runtime_errors_are_caught_with_message_and_line() {
+    let source = "
+fn main() {
+    let e = try {
+        [1, 2] + 3
+    } catch e { e };
+    [e.message, e.line]
+}
+";
+    assert_eq!(
+        eval(source).unwrap(),
+        "[\"Bad or mismatched arguments to +\", 4]"
+    );
+}
+
+#[test]
+fn throwing_unwinds_calls_loops_and_locals() {
+    let source = "
+fn deep(n) {
+    let local = [n];
+    if n == 0 {
+        throw \"bottom\";
+    };
+    deep(n - 1)
+}
+
+fn main() {
+    let before = 1;
+    let caught = nil;
+    for i in 0..3 {
+        let inner = i;
+        caught = try { deep(5) } catch e { \"{e} {inner}\" };
+    };
+    let after = 2;
+    [before, caught, after]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[1, \"bottom 2\", 2]");
+}
+
+#[test]
+fn handlers_nest_and_rethrow() {
+    let source = "
+fn main() {
+    try {
+        try { throw 1; } catch e { throw e + 1; }
+    } catch e { e * 10 }
+}
+";
+    assert_eq!(eval(source).unwrap(), "20");
+}
+
+#[test]
+fn break_out_of_a_try_drops_its_handler() {
+    let source = "
+fn main() {
+    loop {
+        try { break; } catch e { nil };
+    };
+    try { throw \"outer\"; } catch e { e }
+}
+";
+    assert_eq!(eval(source).unwrap(), "outer");
+}
+
+#[test]
+fn uncaught_throws_are_runtime_errors() {
+    let source = "
+fn main() {
+    throw \"oops\";
+}
+";
+    assert!(eval(source)
+        .unwrap_err()
+        .starts_with("Runtime Error, line 3: Uncaught exception: oops"));
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use common::eval;
+use nail::vm::{InterpreterError, VM};
+use nail::{no_such_method, ExternalType, ReferenceType, Value, ValueOrRef};
+
+struct Counter {
+    count: f64,
+}
+
+impl ExternalType for Counter {
+    fn type_name(&self) -> &'static str {
+        "Counter"
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["add", "get"]
+    }
+
+    fn get_arity(&self, name: &str) -> Result<usize, InterpreterError> {
+        match name {
+            "add" => Ok(1),
+            "get" => Ok(0),
+            _ => Err(no_such_method(self, name)),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Result<ValueOrRef, InterpreterError> {
+        match (name, args.as_slice()) {
+            ("add", [Value::Number(n)]) => {
+                self.count += n;
+                Ok(ValueOrRef::Value(Value::Nil))
+            }
+            ("add", _) => Err(InterpreterError::RuntimeError(
+                "Counter add expects a number".to_string(),
+                0,
+                Vec::new(),
+            )),
+            ("get", _) => Ok(ValueOrRef::Ref(ReferenceType::Array(vec![Value::Number(
+                self.count,
+            )]))),
+            _ => Err(no_such_method(self, name)),
+        }
+    }
+}
+
+// Holds one value, which may be a reference to another heap object.
+struct Holder {
+    value: Value,
+}
+
+impl ExternalType for Holder {
+    fn type_name(&self) -> &'static str {
+        "Holder"
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["set", "get"]
+    }
+
+    fn get_arity(&self, name: &str) -> Result<usize, InterpreterError> {
+        match name {
+            "set" => Ok(1),
+            "get" => Ok(0),
+            _ => Err(no_such_method(self, name)),
+        }
+    }
+
+    fn call(&mut self, name: &str, mut args: Vec<Value>) -> Result<ValueOrRef, InterpreterError> {
+        match name {
+            "set" => {
+                self.value = args.remove(0);
+                Ok(ValueOrRef::Value(Value::Nil))
+            }
+            "get" => Ok(ValueOrRef::Value(self.value.clone())),
+            _ => Err(no_such_method(self, name)),
+        }
+    }
+
+    fn trace(&self) -> Vec<Value> {
+        vec![self.value.clone()]
+    }
+}
+
+fn run_with_counter(source: &str) -> Result<String, String> {
+    let mut vm = VM::new();
+    vm.register_fn("counter", 0, |heap, _| {
+        let id = heap.allocate(ReferenceType::External(Box::new(Counter { count: 0.0 })));
+        Ok(Value::ReferenceId(id))
+    });
+    match vm.interpret(source) {
+        Ok(value) => Ok(vm.display_value(&value)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[test]
+fn host_types_are_called_with_method_syntax() {
+    let source = "
+fn main() {
+    let c = counter();
+    c:add(2);
+    c:add(3);
+    [c, c:get(), c:methods()]
+}
+";
+    assert_eq!(
+        run_with_counter(source).unwrap(),
+        "[<Counter>, [5], [\"add\", \"get\"]]"
+    );
+}
+
+#[test]
+fn unknown_methods_list_the_available_ones() {
+    let source = "
+fn main() {
+    let c = counter();
+    c:reset()
+}
+";
+    let error = run_with_counter(source).unwrap_err();
+    assert!(
+        error.starts_with("Runtime Error, line 4: Counter has no method reset (methods: add, get)"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn errors_from_host_types_are_reported_at_the_call() {
+    let source = "
+fn main() {
+    let c = counter();
+
+    c:add(\"one\")
+}
+";
+    let error = run_with_counter(source).unwrap_err();
+    assert!(
+        error.starts_with("Runtime Error, line 5: Counter add expects a number"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn errors_from_host_types_can_be_caught() {
+    let source = "
+fn main() {
+    let c = counter();
+    try {
+        c:add(nil);
+        1
+    } catch e {
+        [e.message, e.line]
+    }
+}
+";
+    assert_eq!(
+        run_with_counter(source).unwrap(),
+        "[\"Counter add expects a number\", 5]"
+    );
+}
+
+#[test]
+fn method_arity_is_checked() {
+    let source = "
+fn main() {
+    counter():add(1, 2)
+}
+";
+    let error = run_with_counter(source).unwrap_err();
+    assert!(
+        error.starts_with(
+            "Runtime Error, line 3: Method add of Counter expects 1 arguments but was called with 2."
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn regex_match_returns_the_captures() {
+    let source = "
+fn main() {
+    let r = \"(a+)(x)?b\":regex();
+    [r:match(\"caab\"), r:match(\"xyz\"), r:methods()]
+}
+";
+    assert_eq!(
+        eval(source).unwrap(),
+        "[[\"aab\", \"aa\", nil], nil, [\"match\"]]"
+    );
+}
+
+#[test]
+fn regex_errors_are_runtime_errors() {
+    let bad_argument = "
+fn main() {
+    \"a\":regex():match(1)
+}
+";
+    assert!(eval(bad_argument)
+        .unwrap_err()
+        .starts_with("Runtime Error, line 3: Expected string argument to Regex match"));
+
+    let bad_method = "
+fn main() {
+    \"a\":regex():replace(\"b\")
+}
+";
+    assert!(eval(bad_method)
+        .unwrap_err()
+        .starts_with("Runtime Error, line 3: Regex has no method replace (methods: match)"));
+}
+
+#[test]
+fn values_held_by_host_types_survive_collection() {
+    let mut vm = VM::new();
+    vm.set_gc_threshold(4);
+    vm.register_fn("holder", 0, |heap, _| {
+        let holder = Holder { value: Value::Nil };
+        let id = heap.allocate(ReferenceType::External(Box::new(holder)));
+        Ok(Value::ReferenceId(id))
+    });
+    let source = "
+fn main() {
+    let h = holder();
+    h:set([[1], #{a: 2}]);
+    for i in 0..100 {
+        let garbage = [[i], [i]];
+    };
+    h:get()
+}
+";
+    let result = vm.interpret(source).unwrap();
+    assert_eq!(vm.display_value(&result), "[[1], #{\"a\": 2}]");
+}
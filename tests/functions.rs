@@ -0,0 +1,96 @@
+mod common;
+
+use common::eval;
+
+#[test]
+fn anonymous_functions_are_values() {
+    let source = "
+fn main() {
+    let add = fn(a, b) { a + b };
+    add(2, 3)
+}
+";
+    assert_eq!(eval(source).unwrap(), "5");
+}
+
+#[test]
+fn anonymous_functions_can_be_passed_as_arguments() {
+    let source = "
+fn map(array, f) {
+    let result = [];
+    for _, v in array {
+        result:push(f(v));
+    };
+    result
+}
+
+fn main() {
+    map([1, 2, 3], fn(x) { x * x })
+}
+";
+    assert_eq!(eval(source).unwrap(), "[1, 4, 9]");
+}
+
+#[test]
+fn anonymous_functions_can_take_no_arguments_and_nest() {
+    let source = "
+fn main() {
+    let scale = 10;
+    let make = fn() { fn(x) { x * scale } };
+    make()(4)
+}
+";
+    assert_eq!(eval(source).unwrap(), "40");
+}
+
+#[test]
+fn calling_a_function_with_too_few_arguments_is_an_error() {
+    let source = "
+fn add(a, b) {
+    a + b
+}
+
+fn main() {
+    add(1)
+}
+";
+    let error = eval(source).unwrap_err();
+    assert!(
+        error.starts_with(
+            "Runtime Error, line 7: Function add expects 2 arguments but was called with 1."
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn calling_a_closure_with_too_many_arguments_is_an_error() {
+    let source = "
+fn main() {
+    let n = 1;
+    let f = fn(x) { x + n };
+    f(1, 2)
+}
+";
+    let error = eval(source).unwrap_err();
+    assert!(
+        error.contains("expects 1 arguments but was called with 2."),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn calling_with_the_right_number_of_arguments_still_works() {
+    let source = "
+fn three(a, b, c) {
+    [a, b, c]
+}
+
+fn main() {
+    three(1, 2, 3)
+}
+";
+    assert_eq!(eval(source).unwrap(), "[1, 2, 3]");
+}
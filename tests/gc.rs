@@ -0,0 +1,86 @@
+mod common;
+
+use nail::vm::VM;
+use nail::Value;
+
+const CHURN: &str = "
+fn churn(n) {
+    let keep = [];
+    for i in 0..n {
+        let garbage = [i, [i]];
+        if i % 100 == 0 {
+            keep:push([i, i]);
+        };
+    };
+    keep
+}
+
+fn cycles(n) {
+    for i in 0..n {
+        let a = [];
+        let b = [a];
+        a:push(b);
+    };
+}
+
+fn counter() {
+    let count = 0;
+    fn() {
+        let garbage = [[], []];
+        count += 1;
+        count
+    }
+}
+";
+
+#[test]
+fn garbage_is_reclaimed_and_survivors_stay_live() {
+    let mut vm = VM::new();
+    vm.set_gc_threshold(16);
+    vm.load(CHURN).unwrap();
+    let kept = vm.call("churn", &[Value::Number(1000.0)]).unwrap();
+    let kept: Vec<Vec<f64>> = vm.get(&kept).unwrap();
+    let expected: Vec<Vec<f64>> = (0..10).map(|i| vec![i as f64 * 100.0; 2]).collect();
+    assert_eq!(kept, expected);
+    // 3000 arrays were allocated in all.
+    assert!(vm.live_objects() < 100, "{} live", vm.live_objects());
+}
+
+#[test]
+fn cycles_are_collected() {
+    let mut vm = VM::new();
+    vm.load(CHURN).unwrap();
+    let before = vm.live_objects();
+    let result = vm.call("cycles", &[Value::Number(100.0)]).unwrap();
+    vm.release(result);
+    assert_eq!(vm.live_objects(), before + 200);
+    assert_eq!(vm.collect_garbage(), 200);
+    assert_eq!(vm.live_objects(), before);
+}
+
+#[test]
+fn closures_and_upvalues_survive_collection() {
+    let mut vm = VM::new();
+    vm.set_gc_threshold(4);
+    let result = vm.interpret(&format!(
+        "{}
+fn main() {{
+    let next = counter();
+    next();
+    next();
+    next()
+}}",
+        CHURN
+    ));
+    assert_eq!(result.unwrap(), Value::Number(3.0));
+}
+
+#[test]
+fn collecting_with_nothing_running_frees_everything_unreachable() {
+    let mut vm = VM::new();
+    vm.load(CHURN).unwrap();
+    let result = vm.call("churn", &[Value::Number(10.0)]).unwrap();
+    vm.release(result);
+    vm.collect_garbage();
+    assert_eq!(vm.live_objects(), 0);
+}
@@ -0,0 +1,84 @@
+use nail::vm::{InterpreterError, VM};
+use nail::Value;
+
+const PLUGIN: &str = "
+let calls = 0;
+
+fn make(n) {
+    calls += 1;
+    let out = [];
+    for i in 0..n {
+        out:push([i]);
+    };
+    out
+}
+
+fn count() { calls }
+";
+
+#[test]
+fn call_runs_a_loaded_function_with_arguments() {
+    let mut vm = VM::new();
+    vm.load(PLUGIN).unwrap();
+    let result = vm.call("make", &[Value::Number(3.0)]).unwrap();
+    assert_eq!(
+        vm.display_value(vm.handle_value(&result)),
+        "[[0], [1], [2]]"
+    );
+    vm.call("make", &[Value::Number(0.0)]).unwrap();
+    let calls = vm.call("count", &[]).unwrap();
+    assert_eq!(vm.get::<f64>(&calls).unwrap(), 2.0);
+}
+
+#[test]
+fn call_results_survive_later_collections() {
+    let mut vm = VM::new();
+    vm.set_gc_threshold(8);
+    vm.load(PLUGIN).unwrap();
+    let first = vm.call("make", &[Value::Number(5.0)]).unwrap();
+    for _ in 0..20 {
+        let garbage = vm.call("make", &[Value::Number(50.0)]).unwrap();
+        vm.release(garbage);
+    }
+    vm.collect_garbage();
+    assert_eq!(
+        vm.get::<Vec<Vec<f64>>>(&first).unwrap(),
+        vec![vec![0.0], vec![1.0], vec![2.0], vec![3.0], vec![4.0]]
+    );
+    vm.release(first);
+    vm.collect_garbage();
+    assert_eq!(vm.live_objects(), 0);
+}
+
+#[test]
+fn calling_an_undefined_function_is_a_distinct_error() {
+    let mut vm = VM::new();
+    vm.load(PLUGIN).unwrap();
+    match vm.call("missing", &[]) {
+        Err(InterpreterError::UndefinedFunction(name)) => assert_eq!(name, "missing"),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn call_checks_arity() {
+    let mut vm = VM::new();
+    vm.load(PLUGIN).unwrap();
+    let e = vm.call("make", &[]).unwrap_err().to_string();
+    assert!(
+        e.contains("Function make expects 1 arguments but was called with 0."),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn runtime_errors_in_calls_are_returned() {
+    let mut vm = VM::new();
+    vm.load(PLUGIN).unwrap();
+    let e = vm.call("make", &[Value::Nil]).unwrap_err();
+    assert!(matches!(e, InterpreterError::RuntimeError(..)), "{:?}", e);
+    // The VM is still usable afterwards.
+    let calls = vm.call("count", &[]).unwrap();
+    assert_eq!(vm.get::<f64>(&calls).unwrap(), 1.0);
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use common::{eval, nailc, temp_dir, write_file};
+use nail::vm::VM;
+
+// A main that adds each of 0..n to a total, using n distinct constants.
+fn sum_of_constants(n: usize) -> String {
+    let adds: Vec<_> = (0..n).map(|i| format!("    total += {};\n", i)).collect();
+    format!(
+        "fn main() {{\n    let total = 0;\n{}    total\n}}",
+        adds.concat()
+    )
+}
+
+#[test]
+fn more_than_256_constants_are_loaded_correctly() {
+    assert_eq!(eval(&sum_of_constants(300)).unwrap(), "44850");
+
+    let strings: Vec<_> = (0..300).map(|i| format!("\"s{}\"", i)).collect();
+    let source = format!(
+        "fn main() {{ let a = [{}]; [a[0], a[255], a[256], a[299]] }}",
+        strings.join(", ")
+    );
+    assert_eq!(
+        eval(&source).unwrap(),
+        "[\"s0\", \"s255\", \"s256\", \"s299\"]"
+    );
+}
+
+#[test]
+fn more_than_65536_constants_are_loaded_correctly() {
+    assert_eq!(eval(&sum_of_constants(70000)).unwrap(), "2449965000");
+}
+
+#[test]
+fn identical_constants_share_a_slot() {
+    let adds = "    total += 1.5;\n    total = total + \"x\":len();\n".repeat(1000);
+    let source = format!("fn main() {{\n    let total = 0;\n{}    total\n}}", adds);
+    let mut vm = VM::new();
+    vm.load(&source).unwrap();
+    assert!(vm.chunk().constants.len() < 10);
+    let total = vm.call("main", &[]).unwrap();
+    assert_eq!(vm.get::<f64>(&total).unwrap(), 2500.0);
+}
+
+// Enough statements to push every jump around them past the 16 bit range.
+fn filler() -> String {
+    "        total += 1;\n".repeat(10000)
+}
+
+#[test]
+fn jumps_over_large_bodies_are_widened() {
+    let source = format!(
+        "
+fn main() {{
+    let total = 0;
+    let i = 0;
+    while i < 3 {{
+        i += 1;
+        if i == 2 {{
+            continue;
+        }};
+{0}
+        if total > 100000 {{
+            break;
+        }};
+    }};
+    for j in 0..2 {{
+{0}
+    }};
+    if total < 0 || total > 1 && total < 2 {{
+{0}
+    }} else {{
+        total += 0.5;
+    }};
+    let caught = try {{
+{0}
+        throw 7;
+    }} catch e {{
+        e
+    }};
+    [total, caught]
+}}
+",
+        filler()
+    );
+    assert_eq!(eval(&source).unwrap(), "[50000.5, 7]");
+}
+
+#[test]
+fn wide_jumps_are_only_used_when_needed() {
+    let dir = temp_dir();
+    let small = write_file(&dir, "small.nlx", "fn main() { loop { break; }; }");
+    let run = nailc(&["disasm", small.to_str().unwrap()], "");
+    assert!(run.stdout.contains("OP_JUMP "), "{}", run.stdout);
+    assert!(!run.stdout.contains("_LONG"), "{}", run.stdout);
+
+    let source = format!(
+        "fn main() {{ let total = 0; loop {{\n{}        break;\n    }}; total }}",
+        filler()
+    );
+    let large = write_file(&dir, "large.nlx", &source);
+    let run = nailc(&["disasm", large.to_str().unwrap()], "");
+    assert!(
+        run.stdout.contains("OP_JUMP_LONG"),
+        "{}",
+        &run.stdout[..200]
+    );
+    assert_eq!(eval(&source).unwrap(), "10000");
+}
+
+fn numbered_lets(prefix: &str, n: usize) -> String {
+    (0..n)
+        .map(|i| format!("    let {}{} = {};\n", prefix, i, i))
+        .collect()
+}
+
+#[test]
+fn more_than_256_locals_are_loaded_and_assigned() {
+    let source = format!(
+        "fn main() {{\n{}    v299 += 1000;\n    let f = fn() {{ v256 }};\n    [v0, v255, v256, v299, f()]\n}}",
+        numbered_lets("v", 300)
+    );
+    assert_eq!(eval(&source).unwrap(), "[0, 255, 256, 1299, 256]");
+}
+
+#[test]
+fn local_slots_are_reused_after_a_block_ends() {
+    let blocks: Vec<_> = (0..70)
+        .map(|_| {
+            format!(
+                "    {{\n{}    total += v999;\n    }};\n",
+                numbered_lets("v", 1000)
+            )
+        })
+        .collect();
+    let source = format!(
+        "fn main() {{\n    let total = 0;\n{}    total\n}}",
+        blocks.concat()
+    );
+    assert_eq!(eval(&source).unwrap(), "69930");
+}
+
+#[test]
+fn locals_are_limited_to_65535_per_function() {
+    let source = format!("fn main() {{\n{}    v65534\n}}", numbered_lets("v", 65535));
+    assert_eq!(eval(&source).unwrap(), "65534");
+
+    let source = format!("fn main() {{\n{}}}", numbered_lets("v", 65536));
+    assert_eq!(
+        eval(&source).unwrap_err(),
+        "Compiler error: Too many local variables in function main"
+    );
+}
+
+#[test]
+fn more_than_256_functions_can_be_called() {
+    let functions: Vec<_> = (0..300)
+        .map(|i| format!("fn f{}(x) {{ x + {} }}\n", i, i))
+        .collect();
+    let source = format!(
+        "{}fn main() {{ [f0(1), f255(1), f256(1), f299(1)] }}",
+        functions.concat()
+    );
+    assert_eq!(eval(&source).unwrap(), "[1, 256, 257, 300]");
+}
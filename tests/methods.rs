@@ -0,0 +1,85 @@
+mod common;
+
+use common::eval;
+
+const COUNTER: &str = "
+struct Counter { count }
+
+impl Counter {
+    fn add(self, n) {
+        self.count += n;
+        self
+    }
+
+    fn get(self) {
+        self.count
+    }
+}
+";
+
+#[test]
+fn methods_receive_self_and_can_mutate_it() {
+    let source = format!(
+        "{}
+fn main() {{
+    let c = Counter {{ count: 1 }};
+    c:add(2):add(3);
+    c:get()
+}}",
+        COUNTER
+    );
+    assert_eq!(eval(&source).unwrap(), "6");
+}
+
+#[test]
+fn user_methods_take_priority_over_builtins() {
+    let source = "
+struct P { x }
+
+impl P {
+    fn to_string(self) {
+        \"P({self.x})\"
+    }
+}
+
+fn main() {
+    P { x: 4 }:to_string()
+}
+";
+    assert_eq!(eval(source).unwrap(), "P(4)");
+}
+
+#[test]
+fn method_arity_errors_do_not_count_the_receiver() {
+    let source = format!(
+        "{}
+fn main() {{
+    let c = Counter {{ count: 1 }};
+    c:add(1, 2)
+}}",
+        COUNTER
+    );
+    let error = eval(&source).unwrap_err();
+    assert!(
+        error.starts_with(
+            "Runtime Error, line 17: Method Counter::add expects 1 arguments but was called with 2."
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn methods_must_take_self() {
+    let source = "
+struct P { x }
+impl P {
+    fn nothing() { nil }
+}
+fn main() {}
+";
+    assert_eq!(
+        eval(source).unwrap_err(),
+        "Compiler error: Method P::nothing must take self as its first argument"
+    );
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use common::{temp_dir, write_file};
+use nail::vm::VM;
+
+// Write each file into a new directory and run main.nlx.
+fn eval_files(files: &[(&str, &str)]) -> Result<String, String> {
+    let dir = temp_dir();
+    for (name, source) in files {
+        if let Some(parent) = dir.join(name).parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        write_file(&dir, name, source);
+    }
+    let main = dir.join("main.nlx");
+    let source = std::fs::read_to_string(&main).unwrap();
+    let mut vm = VM::new();
+    match vm.interpret_file(&source, &main) {
+        Ok(value) => Ok(vm.display_value(&value)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+const GRID: &str = "
+let size = 5;
+
+fn in_bounds(p) {
+    p[0] >= 0 && p[0] < size && p[1] >= 0 && p[1] < size
+}
+";
+
+#[test]
+fn module_members_are_accessed_through_the_module_name() {
+    let main = "
+import \"lib/grid.nlx\";
+
+fn main() {
+    let before = grid.in_bounds([4, 4]);
+    grid.size = 2;
+    [before, grid.in_bounds([4, 4]), grid.size]
+}
+";
+    assert_eq!(
+        eval_files(&[("main.nlx", main), ("lib/grid.nlx", GRID)]).unwrap(),
+        "[true, false, 2]"
+    );
+}
+
+#[test]
+fn modules_can_declare_the_same_struct_and_enum_names() {
+    let a = "
+struct Point { x, y }
+enum Kind { One(v), Two }
+
+impl Point {
+    fn sum(self) { self.x + self.y }
+}
+
+fn make() { Point { x: 1, y: 2 } }
+fn kind() { Kind::One(10) }
+fn value(k) { match k { Kind::One(v) => v, Kind::Two => 0 } }
+";
+    let b = "
+struct Point { z }
+enum Kind { Two, One }
+
+impl Point {
+    fn sum(self) { self.z * 100 }
+}
+
+fn make() { Point { z: 3 } }
+fn kind() { Kind::One }
+fn value(k) { match k { Kind::One => 1, Kind::Two => 2 } }
+";
+    let main = "
+import \"a.nlx\";
+import \"b.nlx\";
+
+struct Point { w }
+
+fn main() {
+    let mine = Point { w: 4 };
+    [a.make():sum(), b.make():sum(), mine.w, a.value(a.kind()), b.value(b.kind())]
+}
+";
+    assert_eq!(
+        eval_files(&[("main.nlx", main), ("a.nlx", a), ("b.nlx", b)]).unwrap(),
+        "[3, 300, 4, 10, 1]"
+    );
+}
+
+#[test]
+fn missing_members_and_modules_are_compile_errors() {
+    let main = "
+import \"grid.nlx\";
+fn main() { grid.nothing }
+";
+    assert_eq!(
+        eval_files(&[("main.nlx", main), ("grid.nlx", GRID)]).unwrap_err(),
+        "Compiler error: Module grid has no member nothing (line 3)"
+    );
+
+    let main = "import \"missing.nlx\";\nfn main() {}";
+    assert_eq!(
+        eval_files(&[("main.nlx", main)]).unwrap_err(),
+        "Compiler error: Could not find module missing.nlx (line 1)"
+    );
+}
+
+#[test]
+fn import_cycles_are_compile_errors() {
+    let a = "import \"b.nlx\";\nfn f() { 1 }";
+    let b = "import \"a.nlx\";\nfn g() { 2 }";
+    let main = "import \"a.nlx\";\nfn main() {}";
+    let error = eval_files(&[("main.nlx", main), ("a.nlx", a), ("b.nlx", b)]).unwrap_err();
+    assert!(error.contains("Import cycle"), "{}", error);
+}
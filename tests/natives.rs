@@ -0,0 +1,90 @@
+use nail::vm::{InterpreterError, VM};
+use nail::{FromNail, IntoNail, ReferenceType, Value};
+
+fn error(message: &str) -> InterpreterError {
+    InterpreterError::RuntimeError(message.to_string(), 0, Vec::new())
+}
+
+#[test]
+fn natives_are_called_like_script_functions() {
+    let mut vm = VM::new();
+    vm.register_fn("add", 2, |_, args| match (&args[0], &args[1]) {
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+        _ => Err(error("add expects numbers")),
+    });
+    let result = vm
+        .interpret("fn main() { let f = add; [add(1, 2), f(3, 4)] }")
+        .unwrap();
+    assert_eq!(vm.display_value(&result), "[3, 7]");
+}
+
+#[test]
+fn natives_can_allocate_and_read_heap_objects() {
+    let mut vm = VM::new();
+    vm.register_fn("range", 1, |heap, args| {
+        let n = usize::from_nail(&args[0], heap)?;
+        (0..n).collect::<Vec<_>>().into_nail(heap)
+    });
+    vm.register_fn("first", 1, |heap, args| match &args[0] {
+        Value::ReferenceId(id) => match heap.get(*id) {
+            Some(ReferenceType::Array(a)) => Ok(a.first().cloned().unwrap_or(Value::Nil)),
+            _ => Err(error("first expects an array")),
+        },
+        _ => Err(error("first expects an array")),
+    });
+    let result = vm
+        .interpret("fn main() { let r = range(4); [r, first(r), first([])] }")
+        .unwrap();
+    assert_eq!(vm.display_value(&result), "[[0, 1, 2, 3], 0, nil]");
+}
+
+#[test]
+fn native_allocations_trigger_collection() {
+    let mut vm = VM::new();
+    vm.set_gc_threshold(64);
+    vm.register_fn("garbage", 0, |heap, _| {
+        vec![vec![0.0; 2]; 8].into_nail(heap)
+    });
+    vm.interpret("fn main() { for i in 0..1000 { garbage(); }; }")
+        .unwrap();
+    // Each call leaves 9 unreachable arrays, so without collections there
+    // would be thousands.
+    assert!(vm.live_objects() < 200, "{}", vm.live_objects());
+}
+
+#[test]
+fn native_errors_are_reported_at_the_call() {
+    let mut vm = VM::new();
+    vm.register_fn("fail", 0, |_, _| Err(error("native failure")));
+    let e = vm
+        .interpret("fn main() {\n    fail()\n}")
+        .unwrap_err()
+        .to_string();
+    assert!(
+        e.starts_with("Runtime Error, line 2: native failure"),
+        "{}",
+        e
+    );
+
+    let e = vm
+        .interpret("fn main() {\n    fail(1)\n}")
+        .unwrap_err()
+        .to_string();
+    assert!(
+        e.starts_with(
+            "Runtime Error, line 2: Function fail expects 0 arguments but was called with 1."
+        ),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn script_functions_shadow_natives() {
+    let mut vm = VM::new();
+    vm.register_fn("answer", 0, |_, _| Ok(Value::Number(1.0)));
+    let result = vm
+        .interpret("fn answer() { 42 }\nfn main() { answer() }")
+        .unwrap();
+    assert_eq!(vm.display_value(&result), "42");
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::{nailc, temp_dir, write_file};
+use nail::vm::VM;
+
+fn repl(vm: &mut VM, line: &str) -> String {
+    match vm.interpret_repl(line) {
+        Ok(value) => vm.display_value(&value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn bindings_functions_and_objects_persist_between_entries() {
+    let mut vm = VM::new();
+    repl(&mut vm, "let xs = [1];");
+    repl(&mut vm, "fn push_twice(a, v) { a:push(v); a:push(v); }");
+    repl(&mut vm, "push_twice(xs, 2);");
+    assert_eq!(repl(&mut vm, "xs"), "[1, 2, 2]");
+    repl(&mut vm, "xs = xs:len() * 10;");
+    assert_eq!(repl(&mut vm, "xs + 1"), "31");
+}
+
+#[test]
+fn objects_survive_garbage_collection_between_entries() {
+    let mut vm = VM::new();
+    vm.set_gc_threshold(4);
+    repl(&mut vm, "let keep = #{a: [1, 2]};");
+    for _ in 0..10 {
+        repl(&mut vm, "let garbage = [[], [], []];");
+    }
+    vm.collect_garbage();
+    assert_eq!(repl(&mut vm, "keep.a"), "[1, 2]");
+}
+
+#[test]
+fn an_error_does_not_lose_earlier_definitions() {
+    let mut vm = VM::new();
+    repl(&mut vm, "let n = 1;");
+    assert!(repl(&mut vm, "undefined_thing").starts_with("Compiler error"));
+    assert!(repl(&mut vm, "n + nil").starts_with("Runtime Error"));
+    assert_eq!(repl(&mut vm, "n"), "1");
+}
+
+#[test]
+fn the_repl_reads_multi_line_input() {
+    let input = "fn double(a) {\n    a * 2\n}\ndouble(21)\n\"two\nlines\"\n";
+    let run = nailc(&[], input);
+    assert_eq!(run.status, 0);
+    assert!(run.stdout.contains("42\n"), "{}", run.stdout);
+    assert!(run.stdout.contains("two\nlines\n"), "{}", run.stdout);
+}
+
+#[test]
+fn reset_forgets_definitions() {
+    let run = nailc(&[], "let x = 1;\nx\n:reset\nx\n");
+    assert!(run.stdout.contains("1\n"), "{}", run.stdout);
+    assert!(
+        run.stdout.contains("Compiler error: Undefined variable: x"),
+        "{}",
+        run.stdout
+    );
+}
+
+#[test]
+fn load_and_history_commands() {
+    let dir = temp_dir();
+    let lib = write_file(&dir, "lib.nlx", "fn triple(x) { x * 3 }\nlet loaded = 5;\n");
+    let input = format!(":load {}\ntriple(loaded)\n:history\n", lib.display());
+    let run = nailc(&[], &input);
+    assert!(run.stdout.contains("15\n"), "{}", run.stdout);
+    assert!(
+        run.stdout.contains("   2 triple(loaded)\n"),
+        "{}",
+        run.stdout
+    );
+}
+
+#[test]
+fn destructuring_lets_define_globals() {
+    let mut vm = VM::new();
+    assert_eq!(repl(&mut vm, "let [a, b] = [1, 2];"), "nil");
+    assert_eq!(repl(&mut vm, "[a, b]"), "[1, 2]");
+    repl(&mut vm, "let #{x, y: [_, ..rest]} = #{x: 3, y: [4, 5, 6]};");
+    repl(&mut vm, "fn total() { a + b + x + rest[1] }");
+    assert_eq!(repl(&mut vm, "[rest, total()]"), "[[5, 6], 12]");
+    repl(&mut vm, "let [a, b] = [b, a];");
+    assert_eq!(repl(&mut vm, "[a, b]"), "[2, 1]");
+    assert!(repl(&mut vm, "let [c] = 5;").starts_with("Runtime Error"));
+    assert_eq!(repl(&mut vm, "a"), "2");
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::eval;
+use nail::vm::{StackLimits, VmOptions, VM};
+use nail::Value;
+
+fn eval_with_limits(limits: StackLimits, source: &str) -> Result<String, String> {
+    let mut vm = VM::with_options(VmOptions {
+        limits,
+        ..VmOptions::default()
+    });
+    match vm.interpret(source) {
+        Ok(value) => Ok(vm.display_value(&value)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+const RECURSE: &str = "
+fn down(n) {
+    let a = n;
+    let b = n;
+    [n, down(n + 1)]
+}
+
+fn main() {
+    down(0)
+}
+";
+
+#[test]
+fn unbounded_recursion_is_a_stack_overflow() {
+    let error = eval(RECURSE).unwrap_err();
+    assert!(
+        error.starts_with(
+            "Runtime Error, line 5: Stack overflow in function down at call depth 65536 (call depth limit is 65536)"
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn deep_recursion_within_the_limits_works() {
+    let source = "
+fn count(n) {
+    if n == 0 {
+        0
+    } else {
+        1 + count(n - 1)
+    }
+}
+
+fn main() {
+    count(50000)
+}
+";
+    assert_eq!(eval(source).unwrap(), "50000");
+}
+
+#[test]
+fn each_limit_is_reported() {
+    let limits = StackLimits {
+        value_stack: 10000,
+        locals: 10000,
+        call_depth: 1000,
+    };
+    let error = eval_with_limits(limits, RECURSE).unwrap_err();
+    assert!(error.contains("(call depth limit is 1000)"), "{}", error);
+
+    let limits = StackLimits {
+        locals: 100,
+        ..limits
+    };
+    let error = eval_with_limits(limits, RECURSE).unwrap_err();
+    assert!(
+        error.contains("Stack overflow in function down at call depth 34 (locals limit is 100)"),
+        "{}",
+        error
+    );
+
+    let limits = StackLimits {
+        value_stack: 100,
+        locals: 10000,
+        call_depth: 1000,
+    };
+    let error = eval_with_limits(limits, RECURSE).unwrap_err();
+    assert!(error.contains("(value stack limit is 100)"), "{}", error);
+}
+
+#[test]
+fn the_value_stack_limit_applies_within_a_function() {
+    let limits = StackLimits {
+        value_stack: 50,
+        ..StackLimits::default()
+    };
+    let numbers: Vec<_> = (0..100).map(|i| i.to_string()).collect();
+    let source = format!("fn main() {{\n    count({})\n}}", numbers.join(", "));
+    let mut vm = VM::with_options(VmOptions {
+        limits,
+        ..VmOptions::default()
+    });
+    vm.register_fn("count", 100, |_, args| Ok(Value::Number(args.len() as f64)));
+    let error = vm.interpret(&source).unwrap_err().to_string();
+    assert!(
+        error.starts_with(
+            "Runtime Error, line 2: Stack overflow in function main at call depth 0 (value stack limit is 50)"
+        ),
+        "{}",
+        error
+    );
+
+    let limits = StackLimits {
+        value_stack: 200,
+        ..limits
+    };
+    let mut vm = VM::with_options(VmOptions {
+        limits,
+        ..VmOptions::default()
+    });
+    vm.register_fn("count", 100, |_, args| Ok(Value::Number(args.len() as f64)));
+    let result = vm.interpret(&source).unwrap();
+    assert_eq!(vm.display_value(&result), "100");
+}
+
+#[test]
+fn stack_overflows_can_be_caught() {
+    let source = "
+fn down(n) {
+    down(n + 1)
+}
+
+fn main() {
+    let message = try { down(0) } catch e { e.message };
+    [message, 1 + 1]
+}
+";
+    let limits = StackLimits {
+        call_depth: 100,
+        ..StackLimits::default()
+    };
+    assert_eq!(
+        eval_with_limits(limits, source).unwrap(),
+        "[\"Stack overflow in function down at call depth 100 (call depth limit is 100)\", 2]"
+    );
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::eval;
+
+#[test]
+fn interpolation_displays_each_expression() {
+    let source = "
+struct P { x }
+
+fn main() {
+    let name = \"world\";
+    let xs = [1, \"two\"];
+    \"hello {name}! {1 + 2} {xs} {P { x: nil }.x}\"
+}
+";
+    assert_eq!(eval(source).unwrap(), "hello world! 3 [1, \"two\"] nil");
+}
+
+#[test]
+fn interpolations_can_nest() {
+    let source = "
+fn main() {
+    let inner = \"b\";
+    \"a{\"<{inner}>\"}c\"
+}
+";
+    assert_eq!(eval(source).unwrap(), "a<b>c");
+}
+
+#[test]
+fn long_interpolations_keep_every_part() {
+    let parts: Vec<_> = (0..300).map(|i| format!("{{{}}},", i)).collect();
+    let source = format!("fn main() {{ \"{}\" }}", parts.concat());
+    let expected: Vec<_> = (0..300).map(|i| format!("{},", i)).collect();
+    assert_eq!(eval(&source).unwrap(), expected.concat());
+}
+
+#[test]
+fn escaped_braces_are_literal() {
+    let source = r#"fn main() { "braces \{ literal \} {1 + 1}" }"#;
+    assert_eq!(eval(source).unwrap(), "braces { literal } 2");
+}
+
+#[test]
+fn escapes_are_decoded_left_to_right() {
+    // A backslash followed by a quote, then a backslash followed by n.
+    let source = r#"fn main() { ["\\\"", "\\n", "a\tb", "\q"] }"#;
+    assert_eq!(eval(source).unwrap(), r#"["\\\"", "\\n", "a\tb", "\\q"]"#);
+}
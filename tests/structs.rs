@@ -0,0 +1,160 @@
+mod common;
+
+use common::eval;
+
+#[test]
+fn struct_fields_can_be_read_and_assigned() {
+    let source = "
+struct Point { x, y }
+
+fn main() {
+    let p = Point { y: 2, x: 1 };
+    p.x += 10;
+    p.y = p.x * 2;
+    [p.x, p.y]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[11, 22]");
+}
+
+#[test]
+fn structs_sharing_field_names_keep_their_own_layout() {
+    let source = "
+struct A { x, y }
+struct B { y, z, x }
+
+fn main() {
+    let a = A { x: 1, y: 2 };
+    let b = B { x: 3, y: 4, z: 5 };
+    [a.x, a.y, b.x, b.y, b.z]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[1, 2, 3, 4, 5]");
+}
+
+#[test]
+fn struct_literals_must_initialize_each_field_once() {
+    let missing = "
+struct Point { x, y }
+fn main() { Point { x: 1 } }
+";
+    assert_eq!(
+        eval(missing).unwrap_err(),
+        "Compiler error: Missing fields in Point literal: y"
+    );
+    let twice = "
+struct Point { x, y }
+fn main() { Point { x: 1, y: 2, x: 3 } }
+";
+    assert_eq!(
+        eval(twice).unwrap_err(),
+        "Compiler error: Field x initialized twice in Point literal"
+    );
+}
+
+#[test]
+fn reading_an_undeclared_field_of_an_instance_is_a_runtime_error() {
+    let source = "
+struct P { x }
+
+fn main() {
+    let p = P { x: 1 };
+    p.y
+}
+";
+    assert!(eval(source)
+        .unwrap_err()
+        .starts_with("Runtime Error, line 6: Struct P has no field y"));
+}
+
+#[test]
+fn assigning_an_undeclared_field_of_an_instance_is_a_runtime_error() {
+    let source = "
+struct P { x }
+struct Q { y }
+
+fn main() {
+    let p = P { x: 1 };
+    p.y = 2;
+}
+";
+    assert!(eval(source)
+        .unwrap_err()
+        .starts_with("Runtime Error, line 7: Struct P has no field y"));
+}
+
+#[test]
+fn field_syntax_works_on_maps() {
+    let source = "
+struct P { x }
+
+fn main() {
+    let m = #{x: 1, other: 2};
+    m.added = 3;
+    m.x += 10;
+    [m.x, m.other, m.added, m.missing]
+}
+";
+    assert_eq!(eval(source).unwrap(), "[11, 2, 3, nil]");
+}
+
+fn numbered(prefix: &str, n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("{}{}", prefix, i)).collect()
+}
+
+#[test]
+fn a_struct_can_have_256_fields() {
+    let fields = numbered("f", 256);
+    let initializers: Vec<_> = fields.iter().map(|f| format!("{}: 1", f)).collect();
+    let source = format!(
+        "struct Big {{ {} }}\nfn main() {{ let b = Big {{ {} }}; b.f255 = 7; [b.f0, b.f255] }}",
+        fields.join(", "),
+        initializers.join(", ")
+    );
+    assert_eq!(eval(&source).unwrap(), "[1, 7]");
+}
+
+#[test]
+fn too_many_fields_is_a_compile_error() {
+    let source = format!(
+        "struct Big {{ {} }}\nfn main() {{}}",
+        numbered("f", 300).join(", ")
+    );
+    assert_eq!(
+        eval(&source).unwrap_err(),
+        "Compiler error: Too many fields in struct Big"
+    );
+
+    let source = format!(
+        "struct A {{ {} }}\nstruct B {{ {} }}\nfn main() {{}}",
+        numbered("a", 200).join(", "),
+        numbered("b", 100).join(", ")
+    );
+    assert!(eval(&source)
+        .unwrap_err()
+        .starts_with("Compiler error: Too many distinct field names"));
+}
+
+#[test]
+fn too_many_structs_is_a_compile_error() {
+    let structs: Vec<_> = numbered("S", 257)
+        .iter()
+        .map(|s| format!("struct {} {{ x }}", s))
+        .collect();
+    let source = format!("{}\nfn main() {{}}", structs.join("\n"));
+    assert!(eval(&source)
+        .unwrap_err()
+        .starts_with("Compiler error: Too many structs"));
+}
+
+#[test]
+fn maps_can_use_more_than_256_distinct_field_names() {
+    let assignments: Vec<_> = (0..300)
+        .map(|i| format!("    m.k{} = {};\n", i, i))
+        .collect();
+    let source = format!(
+        "struct P {{ x }}\nfn main() {{\n    let m = #{{}};\n{}    m.k299 += m.k0 + m.k256;\n    [m.k1, m.k255, m.k299]\n}}",
+        assignments.concat()
+    );
+    assert_eq!(eval(&source).unwrap(), "[1, 255, 555]");
+}